@@ -0,0 +1,201 @@
+use crate::parser::Span;
+use std::error::Error;
+use std::fmt;
+
+// The different ways running a program can fail.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RuntimeErrorKind {
+  // An identifier was read before any `let` bound it.
+  UndefinedVariable(String),
+  // A call named a function that was never defined.
+  UndefinedFunction(String),
+  // An operator was applied to values of types it does not support.
+  TypeMismatch { operator: String, left: &'static str, right: &'static str },
+  // A unary or logical operator was applied to a value of a type it does not support.
+  InvalidOperand { operator: String, found: &'static str },
+  // An `if` or `else if` condition evaluated to something other than a boolean.
+  NonBooleanCondition(&'static str),
+  // A function was called with the wrong number of arguments.
+  ArityMismatch { function: String, expected: usize, found: usize },
+  // A function was defined with two parameters of the same name.
+  DuplicateParameter(String),
+  // An assignment targeted a name declared with `const`.
+  AssignToConstant(String),
+  // Calls were nested more than `depth` deep, either past the call depth limit or as deep as the nesting
+  // depth allows. `calls` lists the functions being called, outermost first, ending with the call that
+  // went over the limit.
+  StackOverflow { depth: usize, calls: Vec<String> },
+  // Nodes were evaluated inside each other more than `depth` deep, as in deeply nested expressions and
+  // blocks, with at most one call running. `calls` lists that call, if any.
+  NestingTooDeep { depth: usize, calls: Vec<String> },
+  // The runtime's fuel ran out before the program finished.
+  OutOfFuel,
+  // The values held by the program grew past the runtime's memory limit, in bytes.
+  MemoryLimitExceeded { limit: usize },
+  // The program was stopped through a `CancelHandle`.
+  Cancelled,
+  // The program was still running when its timeout ran out.
+  TimedOut,
+  // A `break` or `continue` ran outside of any loop in its function.
+  OutsideLoop(&'static str),
+  // A `for` loop was given a value it cannot iterate over.
+  NotIterable(&'static str),
+  // A range in a `for` loop had a `step` of zero, so it would never finish.
+  ZeroStep,
+  // The right hand side of `/` was zero.
+  DivisionByZero,
+  // The result of an arithmetic operator did not fit in a number.
+  Overflow(String),
+  // The right hand side of `^` was below zero.
+  NegativeExponent,
+  // An operator the runtime does not know how to evaluate.
+  UnknownOperator(String),
+  // The parse tree had a node where the runtime expected a different kind of node.
+  InvalidNode(&'static str),
+}
+
+// An error raised while running a program, along with where it happened.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RuntimeError {
+  pub kind: RuntimeErrorKind,
+  // The source location of the node that failed, when it is known.
+  pub span: Option<Span>,
+  // The function that was executing when the error occurred.
+  pub function: Option<String>,
+}
+
+impl RuntimeError {
+  pub fn new(kind: RuntimeErrorKind) -> RuntimeError {
+    RuntimeError { kind, span: None, function: None }
+  }
+}
+
+impl From<RuntimeErrorKind> for RuntimeError {
+  fn from(kind: RuntimeErrorKind) -> RuntimeError {
+    RuntimeError::new(kind)
+  }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RuntimeErrorKind::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+      RuntimeErrorKind::UndefinedFunction(name) => write!(f, "undefined function `{}`", name),
+      RuntimeErrorKind::TypeMismatch { operator, left, right } => {
+        write!(f, "cannot apply `{}` to {} and {}", operator, left, right)
+      },
+      RuntimeErrorKind::InvalidOperand { operator, found } => write!(f, "cannot apply `{}` to {}", operator, found),
+      RuntimeErrorKind::NonBooleanCondition(found) => write!(f, "condition must be a bool, found {}", found),
+      RuntimeErrorKind::ArityMismatch { function, expected, found } => {
+        let plural = |count: &usize| if *count == 1 { "" } else { "s" };
+        let verb = if *found == 1 { "was" } else { "were" };
+        write!(f, "function `{}` takes {} argument{} but {} {} given", function, expected, plural(expected), found, verb)
+      },
+      RuntimeErrorKind::DuplicateParameter(name) => write!(f, "parameter `{}` is declared more than once", name),
+      RuntimeErrorKind::AssignToConstant(name) => write!(f, "cannot assign to constant `{}`", name),
+      RuntimeErrorKind::StackOverflow { depth, calls } => {
+        write!(f, "stack overflow: more than {} nested calls", depth)?;
+        write_calls(f, calls)
+      },
+      RuntimeErrorKind::NestingTooDeep { depth, calls } => {
+        write!(f, "stack overflow: more than {} nested evaluations", depth)?;
+        write_calls(f, calls)
+      },
+      RuntimeErrorKind::OutOfFuel => write!(f, "out of fuel"),
+      RuntimeErrorKind::MemoryLimitExceeded { limit } => write!(f, "memory limit of {} bytes exceeded", limit),
+      RuntimeErrorKind::Cancelled => write!(f, "cancelled"),
+      RuntimeErrorKind::TimedOut => write!(f, "timed out"),
+      RuntimeErrorKind::OutsideLoop(keyword) => write!(f, "`{}` outside of a loop", keyword),
+      RuntimeErrorKind::NotIterable(found) => write!(f, "cannot iterate over {}", found),
+      RuntimeErrorKind::ZeroStep => write!(f, "range step cannot be zero"),
+      RuntimeErrorKind::DivisionByZero => write!(f, "division by zero"),
+      RuntimeErrorKind::Overflow(operator) => write!(f, "integer overflow in `{}`", operator),
+      RuntimeErrorKind::NegativeExponent => write!(f, "negative exponent"),
+      RuntimeErrorKind::UnknownOperator(operator) => write!(f, "unknown operator `{}`", operator),
+      RuntimeErrorKind::InvalidNode(expected) => write!(f, "malformed parse tree: expected {}", expected),
+    }
+  }
+}
+
+// Write a chain of calls in parentheses, if there are any.
+fn write_calls(f: &mut fmt::Formatter, calls: &[String]) -> fmt::Result {
+  if calls.is_empty() {
+    return Ok(());
+  }
+  write!(f, " (")?;
+  // Recursion repeats the same function many times in a row, so runs of one name are collapsed.
+  let mut first = true;
+  for run in calls.chunk_by(|a, b| a == b) {
+    if !first {
+      write!(f, " -> ")?;
+    }
+    first = false;
+    match run.len() {
+      1 => write!(f, "{}", run[0])?,
+      count => write!(f, "{} x{}", run[0], count)?,
+    }
+  }
+  write!(f, ")")
+}
+
+impl fmt::Display for RuntimeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.kind)?;
+    if let Some(span) = &self.span {
+      write!(f, " at {}", span)?;
+    }
+    if let Some(function) = &self.function {
+      write!(f, " in function `{}`", function)?;
+    }
+    Ok(())
+  }
+}
+
+impl Error for RuntimeError {}
+
+// An error raised while parsing, pointing at the place the parser could not get past.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+  // Byte offset of the failure in the source.
+  pub offset: usize,
+  // Line and column (both 1-based) of the failure.
+  pub line: u32,
+  pub column: usize,
+  // The constructs that would have been accepted at this point. Empty when nothing specific was expected.
+  pub expected: Vec<&'static str>,
+  // The full text of the source line containing the failure, used to draw the snippet.
+  pub source_line: String,
+}
+
+impl ParseError {
+  // Describe a failure at byte `offset` of `source`, working out the line, column and snippet.
+  pub fn new(source: &str, offset: usize, expected: Vec<&'static str>) -> ParseError {
+    let line_start = source[..offset].rfind('\n').map_or(0, |ix| ix + 1);
+    let line_end = source[offset..].find('\n').map_or(source.len(), |ix| offset + ix);
+    ParseError {
+      offset,
+      line: source[..offset].matches('\n').count() as u32 + 1,
+      column: source[line_start..offset].chars().count() + 1,
+      expected,
+      source_line: source[line_start..line_end].to_string(),
+    }
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.expected.split_last() {
+      None => write!(f, "unexpected input")?,
+      Some((last, [])) => write!(f, "expected {}", last)?,
+      Some((last, rest)) => write!(f, "expected {} or {}", rest.join(", "), last)?,
+    }
+    writeln!(f, " at {}:{}", self.line, self.column)?;
+    // Underline the failure with a caret beneath the offending column, keeping tabs so the caret lines up.
+    let gutter = self.line.to_string();
+    let indent: String = self.source_line.chars().take(self.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    writeln!(f, "{} | {}", gutter, self.source_line)?;
+    write!(f, "{} | {}^", " ".repeat(gutter.len()), indent)
+  }
+}
+
+impl Error for ParseError {}
@@ -1,17 +1,233 @@
-use crate::parser::Node;
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::parser::{Node, Span};
+use crate::resolver::resolve;
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
   String(String),
   Number(i32),
   Bool(bool),
+  // Produced by statements that yield nothing, such as an `if` with no taken branch
+  // or a function that reaches the end of its body without returning.
+  Unit,
 }
 
+impl Value {
+  // The name of this value's type, as used in error messages.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::String(_) => "string",
+      Value::Number(_) => "number",
+      Value::Bool(_) => "bool",
+      Value::Unit => "unit",
+    }
+  }
+}
+
+// Reasons evaluation can stop before a node produces a value. These travel up
+// through `run` via `?` until something handles them. The larger ones are boxed, since
+// every nested `run` keeps a few results on the host stack.
+enum Unwind {
+  // A `return` statement, caught by the `FunctionCall` that is executing the body.
+  Return(Value),
+  // `break` and `continue`, caught by the innermost loop. They remember where they were written
+  // so they can be reported if they escape a function without meeting a loop.
+  Break(Span),
+  Continue(Span),
+  // A `return` whose value is a call, caught by the `FunctionCall` that is executing the body and run in its place.
+  // The function has been looked up and the arguments evaluated already.
+  TailCall(Box<TailCall>),
+  // A runtime error, which propagates out of the interpreter.
+  Error(Box<RuntimeError>),
+}
+
+// A call to make in place of the function that is returning.
+struct TailCall {
+  name: String,
+  function: Rc<Function>,
+  arguments: Vec<Value>,
+}
+
+impl From<RuntimeError> for Unwind {
+  fn from(error: RuntimeError) -> Unwind {
+    Unwind::Error(Box::new(error))
+  }
+}
+
+impl From<RuntimeErrorKind> for Unwind {
+  fn from(kind: RuntimeErrorKind) -> Unwind {
+    Unwind::Error(Box::new(RuntimeError::new(kind)))
+  }
+}
+
+// The values a `for` loop steps through, produced one at a time so a long range is never stored.
+enum Iteration {
+  // Whole numbers from `next` towards `end`. `next` is `None` once counting would overflow.
+  Range { next: Option<i32>, end: i32, step: i32, inclusive: bool },
+  // The characters of a string, each as a one character string.
+  Chars(std::vec::IntoIter<char>),
+}
+
+impl Iterator for Iteration {
+  type Item = Value;
+  fn next(&mut self) -> Option<Value> {
+    match self {
+      Iteration::Range { next, end, step, inclusive } => {
+        let current = (*next)?;
+        let in_range = match (*step > 0, *inclusive) {
+          (true, true) => current <= *end,
+          (true, false) => current < *end,
+          (false, true) => current >= *end,
+          (false, false) => current > *end,
+        };
+        if !in_range {
+          return None;
+        }
+        *next = current.checked_add(*step);
+        Some(Value::Number(current))
+      },
+      Iteration::Chars(chars) => chars.next().map(|c| Value::String(c.to_string())),
+    }
+  }
+}
+
+// A defined function, prepared once when it is defined so that calls only have to read it.
+struct Function {
+  parameters: Vec<String>,
+  body: Vec<Node>,
+}
+
+impl Function {
+  // Split what follows the name in a `FunctionDefine` into the parameter names and the body statements.
+  fn new(definition: &[Node]) -> Function {
+    let (parameters, body) = match definition.split_first() {
+      Some((Node::FunctionArguments { children, .. }, body)) => (children.as_slice(), body),
+      _ => (&[][..], definition),
+    };
+    let parameters = parameters.iter().filter_map(|parameter| match parameter {
+      Node::Identifier { value, .. } => Some(value.clone()),
+      _ => None,
+    }).collect();
+    Function { parameters, body: body.to_vec() }
+  }
+}
+
+// A variable's entry in a frame: its current value, and whether assignments may change it.
+struct Binding {
+  value: Value,
+  mutable: bool,
+}
 
-struct Runtime {
-  functions: HashMap<String, Vec<Node>>,
-  stack: Vec<HashMap<String, Value>>,
+impl Binding {
+  fn variable(value: Value) -> Binding {
+    Binding { value, mutable: true }
+  }
+}
+
+// Roughly how many bytes of memory a value takes up, counting the text of strings.
+fn size_of_value(value: &Value) -> usize {
+  std::mem::size_of::<Value>() + match value {
+    Value::String(text) => text.len(),
+    _ => 0,
+  }
+}
+
+// Roughly how many bytes of memory a scope's bindings take up, counting their names.
+fn size_of_scope(scope: &HashMap<String, Binding>) -> usize {
+  scope.iter().map(|(name, binding)| name.len() + size_of_value(&binding.value)).sum()
+}
+
+// The variables of one function call, as a stack of nested scopes. The first scope holds the
+// parameters, and each block that is running pushes another on top.
+struct Frame {
+  // The function being called. Empty for the global frame at the bottom of the stack.
+  function: String,
+  scopes: Vec<HashMap<String, Binding>>,
+}
+
+impl Frame {
+  // The innermost binding of `name` visible from the current scope.
+  fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+    self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+  }
+  // Bind `name` in the current scope, shadowing any binding in an enclosing one. Returns the binding it
+  // replaced if the name was already declared in this scope.
+  fn declare(&mut self, name: String, binding: Binding) -> Option<Binding> {
+    self.scopes.last_mut().expect("a frame always has a scope").insert(name, binding)
+  }
+}
+
+// What a `Runtime` shares with its `CancelHandle`s. Every program the runtime executes is numbered, so
+// that asking one program to stop can never stop a later one.
+#[derive(Debug, Default)]
+struct Cancellation {
+  // The number of the latest program that was asked to stop.
+  cancelled: AtomicU64,
+}
+
+// Asks a `Runtime` to stop one program: the next one it executes after the handle was made. It can be
+// cloned and sent to other threads.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+  cancellation: Arc<Cancellation>,
+  program: u64,
+}
+
+impl CancelHandle {
+  // Stop the handle's program with a cancelled error as soon as it evaluates another node. If it has not
+  // started yet, it stops as soon as it does. Does nothing once it has finished.
+  pub fn cancel(&self) {
+    self.cancellation.cancelled.fetch_max(self.program, Ordering::SeqCst);
+  }
+}
+
+// How often, in evaluated nodes, the clock is read to check for a timeout. Reading it is slow next to
+// evaluating a node.
+const TIMEOUT_CHECK_INTERVAL: u64 = 256;
+
+// How many calls can be nested by default.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 128;
+
+// How deeply nodes can be evaluated inside each other by default. Every level takes host stack, about
+// half a kilobyte in an optimized build and a few kilobytes in an unoptimized one, so this many fit in a
+// 2 MiB thread stack, the smallest Rust gives a thread by default, with room to spare.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 512;
+
+// Runs parsed programs. Settings are chained onto `Runtime::new()` before calling `execute`.
+pub struct Runtime {
+  // Functions are reference counted so a call can hold on to one while it runs.
+  functions: HashMap<String, Rc<Function>>,
+  // The global frame, followed by one frame per function call that has not returned yet.
+  stack: Vec<Frame>,
+  max_call_depth: usize,
+  // How many `run`s are in progress inside each other, which is what uses up the host's stack.
+  nesting: usize,
+  max_nesting_depth: usize,
+  // How many more nodes may be evaluated, or `None` for no limit.
+  fuel: Option<u64>,
+  // How many nodes have been evaluated over the life of this runtime.
+  fuel_used: u64,
+  // The most memory that variables may hold at once, or `None` for no limit.
+  memory_limit: Option<usize>,
+  // Roughly how many bytes the variables in every frame hold right now.
+  memory_used: usize,
+  // Lets other threads stop a program through a `CancelHandle`, and the number of the latest program.
+  cancellation: Arc<Cancellation>,
+  program: u64,
+  // How long each program may run for, and when the running one has to finish by.
+  timeout: Option<Duration>,
+  deadline: Option<Instant>,
+}
+
+impl Default for Runtime {
+  fn default() -> Runtime {
+    Runtime::new()
+  }
 }
 
 impl Runtime {
@@ -20,180 +236,205 @@ impl Runtime {
     Runtime {
       functions: HashMap::new(),
       stack: Vec::new(),
+      max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+      nesting: 0,
+      max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+      fuel: None,
+      fuel_used: 0,
+      memory_limit: None,
+      memory_used: 0,
+      cancellation: Arc::new(Cancellation::default()),
+      program: 0,
+      timeout: None,
+      deadline: None,
     }
   }
 
-  // Define the `run` method of the `Runtime` struct.
-  pub fn run(&mut self, node: &Node) -> Result<Value, &'static str> {
+  // Limit how many calls can be nested before the program fails with a stack overflow error. Every call
+  // also uses several levels of the nesting depth, so raising this past the default only has an effect
+  // if `with_max_nesting_depth` is raised with it.
+  pub fn with_max_call_depth(mut self, depth: usize) -> Runtime {
+    self.max_call_depth = depth;
+    self
+  }
+
+  // Limit how deeply nodes can be evaluated inside each other before the program fails with a stack
+  // overflow error, instead of exhausting the host's stack. Hosts running the interpreter on a thread with
+  // a larger stack can raise it.
+  pub fn with_max_nesting_depth(mut self, depth: usize) -> Runtime {
+    self.max_nesting_depth = depth;
+    self
+  }
+
+  // Allow `fuel` nodes to be evaluated before running out, which stops a program with an out of fuel error.
+  // Fuel is only spent on evaluation, so a given program always uses the same amount.
+  pub fn with_fuel(mut self, fuel: u64) -> Runtime {
+    self.fuel = Some(fuel);
+    self
+  }
+
+  // Top up the fuel, so further programs can be run after the budget ran low or out. A program that ran out
+  // of fuel cannot carry on from where it stopped; it has to be executed again.
+  pub fn add_fuel(&mut self, fuel: u64) {
+    if let Some(remaining) = &mut self.fuel {
+      *remaining = remaining.saturating_add(fuel);
+    }
+  }
+
+  // The fuel left to spend, or `None` if there is no limit.
+  pub fn remaining_fuel(&self) -> Option<u64> {
+    self.fuel
+  }
+
+  // The fuel spent by every program this runtime has executed, whether or not it has a limit.
+  pub fn fuel_used(&self) -> u64 {
+    self.fuel_used
+  }
+
+  // Fail with a memory limit error rather than let the values a program holds grow past `bytes`.
+  // Sizes are estimates: a value counts its own size plus the text of a string, and a variable adds its name.
+  pub fn with_memory_limit(mut self, bytes: usize) -> Runtime {
+    self.memory_limit = Some(bytes);
+    self
+  }
+
+  // Roughly how many bytes are held by variables right now. This is zero whenever no program is running.
+  pub fn memory_used(&self) -> usize {
+    self.memory_used
+  }
+
+  // Stop each program with a timed out error once it has been running for `timeout`.
+  pub fn with_timeout(mut self, timeout: Duration) -> Runtime {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  // A handle other threads can use to stop the next program this runtime executes. Each program needs a
+  // handle of its own, taken before it is executed.
+  pub fn cancel_handle(&self) -> CancelHandle {
+    CancelHandle { cancellation: self.cancellation.clone(), program: self.program + 1 }
+  }
+
+  // Check `program` and run it, returning the value it produces.
+  pub fn execute(&mut self, program: &Node) -> Result<Value, RuntimeError> {
+    // The program takes its number even if it does not pass the checks, so a handle taken for it does not
+    // carry over to the next one.
+    self.program += 1;
+    resolve(program)?;
+    self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+    let result = finish(self.run(program));
+    self.deadline = None;
+    result
+  }
+
+  // Evaluate `node`, attributing any error to it that does not have a location yet.
+  fn run(&mut self, node: &Node) -> Result<Value, Unwind> {
+    if let Err(kind) = self.step() {
+      return Err(locate(kind.into(), node.span()));
+    }
+    self.nesting += 1;
+    let result = self.evaluate(node);
+    self.nesting -= 1;
+    result.map_err(|unwind| locate(unwind, node.span()))
+  }
+
+  // Account for evaluating one more node, failing if the program has to stop first. This is kept out of
+  // `run` so that its locals do not take up space in every nested `run` stack frame.
+  #[inline(never)]
+  fn step(&mut self) -> Result<(), RuntimeErrorKind> {
+    // Every node evaluated costs one unit of fuel.
+    if let Some(fuel) = &mut self.fuel {
+      if *fuel == 0 {
+        return Err(RuntimeErrorKind::OutOfFuel);
+      }
+      *fuel -= 1;
+    }
+    self.fuel_used += 1;
+    if self.nesting >= self.max_nesting_depth {
+      return Err(self.nesting_too_deep());
+    }
+    if self.cancellation.cancelled.load(Ordering::Relaxed) == self.program {
+      return Err(RuntimeErrorKind::Cancelled);
+    }
+    if let Some(deadline) = self.deadline {
+      if self.fuel_used.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && Instant::now() >= deadline {
+        return Err(RuntimeErrorKind::TimedOut);
+      }
+    }
+    Ok(())
+  }
+
+  // Evaluate a single `Node`, recursing into `run` for its children. Every nested call passes through this
+  // function several times, so anything with more than a few locals lives in its own method to keep this
+  // function's stack frame small.
+  fn evaluate(&mut self, node: &Node) -> Result<Value, Unwind> {
     // Match the type of the input `Node`.
     match node {
-        // If the `Node` is a `Program`, evaluate each of its children in sequence.
-        Node::Program { children } => {
-            for n in children {
-                match n {
-                    // If the child node is a `FunctionDefine`, add it to the list of functions.
-                    Node::FunctionDefine { .. } => {
-                        self.run(n);
-                    },
-                    // If the child node is an `Expression`, add it as the body of a new `main` function.
-                    Node::Expression { .. } => {
-                        self.functions.insert("main".to_string(), vec![Node::FunctionReturn { children: vec![n.clone()] }]);
-                    },
-                    // If the child node is a `Statement`, add it as the body of a new `main` function.
-                    Node::Statement { .. } => {
-                        self.functions.insert("main".to_string(), vec![n.clone()]);
-                    }
-                    // Ignore any other type of child node.
-                    _ => (),
-                }
-            }
-            // Return `Value::Bool(true)` wrapped in a `Result`.
-            Ok(Value::Bool(true))
-        },
+        // If the `Node` is a `Program`, run its top-level code as a script and then call `main` if there is one.
+        Node::Program { children, .. } => self.program(children, node.span()),
         // If the `Node` is a `MathExpression`, evaluate it.
-        Node::MathExpression { name, children } => {
-            // Evaluate the left and right children of the `MathExpression`.
-            match (self.run(&children[0]), self.run(&children[1])) {
-                // If both children are `Number` values, extract their values and evaluate the expression.
-                (Ok(Value::Number(lhs)), Ok(Value::Number(rhs))) => {
-                    match name.as_ref() {
-                        // If the operator is `+`, add the values.
-                        "+" => Ok(Value::Number(lhs + rhs)),
-                        // If the operator is `-`, subtract the values.
-                        "-" => Ok(Value::Number(lhs - rhs)),
-                        // If the operator is `*`, multiply the values.
-                        "*" => Ok(Value::Number(lhs * rhs)),
-                        // If the operator is `/`, divide the values.
-                        "/" => Ok(Value::Number(lhs / rhs)),
-                        // If the operator is `^`, raise the left value to the power of the right value.
-                        "^" => {
-                            let mut result = 1;
-                            for i in 0..rhs {
-                                result = result * lhs;
-                            }
-                            Ok(Value::Number(result))
-                        },
-                        // If the operator is not recognized, return an error message.
-                        _ => Err("Undefined operator"),
-                    }
-                }
-                // If either child is not a `Number` value, return an error message.
-                _ => Err("Cannot do math on String or Bool"),
-            }
-        },
+        Node::MathExpression { name, children, .. } => self.math(name, children),
         // If the `Node` is a `FunctionCall`, evaluate it.
-        Node::FunctionCall { name, children } => {
-            // Extract the input arguments.
-            let in_args = if children.len() > 0 {
-                match &children[0] {
-                    Node::FunctionArguments { children } => {
-                        children
-                    },
-                    _ => children,
-                }
-            } else {
-                children
-            };
-            // Create a new frame for local variables.
-            let mut new_frame = HashMap::new();
-            // Initialize the result to an error message.
-            let mut result: Result<Value, &'static str> = Err("Undefined function");
-            // Save a raw pointer to the `Runtime` instance for use in the nested closure.
-            let rt = self as *mut Runtime;
-            // Find the named function and evaluate its body.
-            match self.functions.get(name) {
-                Some(statements) => {
-                    {
-                        // If the function has input arguments, bind their values to the corresponding parameters.
-                        match statements[0].clone() {
-                            Node::FunctionArguments { children } => {
-                                for (ix, arg) in children.iter().enumerate() {
-                                    // Use unsafe Rust code to call `run` on the input argument and handle any errors.
-                                    unsafe {
-                                        let result = (*rt).run(&in_args[ix])?;
-                                        match arg {
-                                            Node::Expression { children } => {
-                                                match &children[0] {
-                                                    Node::Identifier { value } => {
-                                                        new_frame.insert(value.clone(), result);
-                                                    },
-                                                    _ => (),
-                                                }
-                                            }
-                                            _ => (),
-                                        }
-                                    }
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                    // Push the new frame onto the stack.
-                    self.stack.push(new_frame);
-                    // Evaluate each statement in the function body.
-                    for n in statements.clone() {
-                        result = self.run(&n);
-                    }
-                    // Pop the frame off the stack.
-                    self.stack.pop();
-                },
-                None => (),
-            };
-            // Return the result of evaluating the function.
-            result
-        },
+        Node::FunctionCall { name, children, .. } => self.call(name, children),
         // If the `Node` is a `FunctionDefine`, add it to the list of functions.
-        Node::FunctionDefine { children } => {
+        Node::FunctionDefine { children, .. } => {
             let (head, tail) = children.split_at(1);
-            match &head[0] {
-                Node::Identifier { value } => {
-                    self.functions.insert(value.to_string(), tail.to_vec());
-                },
-                _ => (),
+            if let Node::Identifier { value, .. } = &head[0] {
+                self.functions.insert(value.to_string(), Rc::new(Function::new(tail)));
             }
             Ok(Value::Bool(true))
         },
-        // If the `Node` is a `FunctionReturn`, evaluate its child node.
-        Node::FunctionReturn { children } => {
-            self.run(&children[0])
-        },
-        // If the `Node` is an `Identifier`, look up its value in the current frame.
-        Node::Identifier { value } => {
-            let last = self.stack.len() - 1;
-            match self.stack[last].get(value) {
-                Some(id_value) => Ok(id_value.clone()),
-                None => Err("Undefined variable"),
-            }
-        },
+        // If the `Node` is a `FunctionReturn`, evaluate its child node and unwind to the enclosing call with the result.
+        // Inside a function, `return f(...)` is a tail call: the arguments are evaluated here, and the enclosing
+        // call runs `f` in place of the current function rather than nesting another call.
+        Node::FunctionReturn { children, .. } => self.function_return(children),
+        // If the `Node` is an `Identifier`, look up its value in the current frame or the globals.
+        Node::Identifier { value, .. } => self.identifier(value),
         // If the `Node` is a `Statement`, evaluate its child node.
-        Node::Statement { children } => {
+        Node::Statement { children, .. } => {
             match children[0] {
                 Node::VariableDefine { .. } |
-                Node::FunctionReturn { .. } => {
+                Node::ConstantDefine { .. } |
+                Node::Assignment { .. } |
+                Node::FunctionReturn { .. } |
+                Node::IfChain { .. } |
+                Node::WhileLoop { .. } |
+                Node::ForLoop { .. } |
+                Node::Break { .. } |
+                Node::Continue { .. } => {
                     self.run(&children[0])
                 },
-                _ => Err("Unknown Statement"),
+                _ => Err(RuntimeErrorKind::InvalidNode("statement").into()),
             }
         },
-        // If the `Node` is a `VariableDefine`, evaluate its expression and bind the result to a new variable.
-        Node::VariableDefine { children } => {
-            // Extract the variable name.
-            let name: String = match &children[0] {
-                Node::Identifier { value } => value.clone(),
-                _ => "".to_string(),
-            };
-            // Evaluate the expression.
-            let value = self.run(&children[1])?;
-            // Add the variable to the current frame.
-            let last = self.stack.len() - 1;
-            self.stack[last].insert(name, value.clone());
-            // Return the value.
-            Ok(value)
-        }
+        // If the `Node` is an `IfChain`, test each branch condition in order and run the body of the first one that holds.
+        Node::IfChain { children, .. } => self.if_chain(children),
+        // If the `Node` is a `WhileLoop`, run its body for as long as the condition holds.
+        Node::WhileLoop { children, .. } => self.while_loop(children),
+        // If the `Node` is a `ForLoop`, bind the loop variable to each value in turn and run the body.
+        Node::ForLoop { children, .. } => self.for_loop(children),
+        // If the `Node` is a `Break` or `Continue`, unwind to the innermost enclosing loop.
+        Node::Break { span } => {
+            Err(Unwind::Break(*span))
+        },
+        Node::Continue { span } => {
+            Err(Unwind::Continue(*span))
+        },
+        // If the `Node` is a `Block`, evaluate each of its statements in sequence in a new scope and return the last result.
+        Node::Block { children, .. } => self.block(children),
+        // If the `Node` is a `VariableDefine` or `ConstantDefine`, evaluate its expression and bind the result to a new name.
+        // Declaring a name that is already bound shadows the old binding rather than being rejected.
+        // Only a `let` can be assigned to later.
+        Node::VariableDefine { children, .. } => self.define(children, true),
+        Node::ConstantDefine { children, .. } => self.define(children, false),
+        // If the `Node` is an `Assignment`, update a variable that a `let` already declared.
+        Node::Assignment { name, children, .. } => self.assignment(name, children),
         // If the `Node` is an `Expression`, evaluate its child node.
-        Node::Expression { children } => {
+        Node::Expression { children, .. } => {
             match children[0] {
-                Node::ComparisonExpression { .. } => {
+                Node::ComparisonExpression { .. } |
+                Node::LogicalExpression { .. } |
+                Node::UnaryExpression { .. } => {
                     self.run(&children[0])
                 },
                 Node::MathExpression { .. } |
@@ -204,61 +445,537 @@ impl Runtime {
                 Node::Identifier { .. } => {
                     self.run(&children[0])
                 },
-                _ => Err("Unknown Expression"),
+                _ => Err(RuntimeErrorKind::InvalidNode("expression").into()),
             }
         },
 
         // If the `Node` is a `ComparisonExpression`, evaluate it.
-        Node::ComparisonExpression { name, children } => {
-            let left_value = self.run(&children[0])?;
-            let right_value = self.run(&children[1])?;
-        
-            match (left_value, right_value) {
-                (Value::Number(left), Value::Number(right)) => {
-                    match name.as_str() {
-                        "==" => Ok(Value::Bool(left == right)),
-                        "!=" => Ok(Value::Bool(left != right)),
-                        "<=" => Ok(Value::Bool(left <= right)),
-                        ">=" => Ok(Value::Bool(left >= right)),
-                        "<" => Ok(Value::Bool(left < right)),
-                        ">" => Ok(Value::Bool(left > right)),
-                        _ => Err("Undefined operator"),
-                    }
-                },
-                (Value::Bool(left), Value::Bool(right)) => {
-                    match name.as_str() {
-                        "==" => Ok(Value::Bool(left == right)),
-                        "!=" => Ok(Value::Bool(left != right)),
-                        _ => Err("Invalid comparison operation for booleans"),
-                    }
-                },
-                _ => Err("Invalid comparison operands"),
-            }
-        },
+        Node::ComparisonExpression { name, children, .. } => self.comparison(name, children),
         
+        // If the `Node` is a `LogicalExpression`, evaluate the left operand and only evaluate the right one if the left does not decide the result.
+        Node::LogicalExpression { name, children, .. } => self.logical(name, children),
+        // If the `Node` is a `UnaryExpression`, evaluate its operand and apply the operator.
+        Node::UnaryExpression { name, children, .. } => self.unary(name, children),
         // If the `Node` is a `Number`, wrap its value in a `Value::Number` and return it.
-        Node::Number { value } => {
+        Node::Number { value, .. } => {
             Ok(Value::Number(*value))
         }
         // If the `Node` is a `String`, wrap its value in a `Value::String` and return it.
-        Node::String { value } => {
-            Ok(Value::String(value.clone()))
-        }
+        Node::String { value, .. } => self.string(value),
         // If the `Node` is a `Bool`, wrap its value in a `Value::Bool` and return it.
-        Node::Bool { value } => {
+        Node::Bool { value, .. } => {
             Ok(Value::Bool(*value))
         }
-        // If the `Node` is of an unhandled type, return an error message.
+        // If the `Node` is of an unhandled type, return an error.
         _ => {
-            Err("Unhandled Node")
+            Err(RuntimeErrorKind::InvalidNode("evaluable node").into())
         },
     }
   }
+
+  // Evaluate the left and right children of a `MathExpression` and combine them.
+  fn math(&mut self, name: &str, children: &[Node]) -> Result<Value, Unwind> {
+    let (lhs, rhs) = (self.run(&children[0])?, self.run(&children[1])?);
+    arithmetic(name, lhs, rhs)
+  }
+
+  // Unwind to the enclosing call with the value of a `return`, or with the call to make in its place.
+  fn function_return(&mut self, children: &[Node]) -> Result<Value, Unwind> {
+    if let [Node::FunctionCall { name, children, span }] = children[0].children() {
+      if self.stack.len() > 1 {
+        let (function, arguments) = self.prepare_call(name, children).map_err(|unwind| locate(unwind, *span))?;
+        return Err(Unwind::TailCall(Box::new(TailCall { name: name.clone(), function, arguments })));
+      }
+    }
+    let value = self.run(&children[0])?;
+    Err(Unwind::Return(value))
+  }
+
+  // The value of a variable.
+  fn identifier(&mut self, name: &str) -> Result<Value, Unwind> {
+    match self.lookup(name) {
+      Some(binding) => Ok(binding.value.clone()),
+      None => Err(RuntimeErrorKind::UndefinedVariable(name.to_string()).into()),
+    }
+  }
+
+  // A string literal, which counts towards the memory limit while it is a temporary.
+  fn string(&mut self, value: &str) -> Result<Value, Unwind> {
+    let value = Value::String(value.to_string());
+    self.check_temporary(size_of_value(&value))?;
+    Ok(value)
+  }
+
+  // Bind the value of the expression in `children` to the name before it.
+  fn define(&mut self, children: &[Node], mutable: bool) -> Result<Value, Unwind> {
+    // Extract the variable name.
+    let name: String = match &children[0] {
+      Node::Identifier { value, .. } => value.clone(),
+      _ => "".to_string(),
+    };
+    // Evaluate the expression.
+    let value = self.run(&children[1])?;
+    // Add the variable to the current frame.
+    self.declare(name, Binding { value: value.clone(), mutable })?;
+    // Return the value.
+    Ok(value)
+  }
+
+  // Apply a prefix operator to its operand.
+  fn unary(&mut self, name: &str, children: &[Node]) -> Result<Value, Unwind> {
+    match (name, self.run(&children[0])?) {
+      ("!", Value::Bool(value)) => Ok(Value::Bool(!value)),
+      ("-", Value::Number(value)) => value.checked_neg().map(Value::Number).ok_or_else(|| RuntimeErrorKind::Overflow(name.to_string()).into()),
+      ("!" | "-", other) => Err(RuntimeErrorKind::InvalidOperand { operator: name.to_string(), found: other.type_name() }.into()),
+      _ => Err(RuntimeErrorKind::UnknownOperator(name.to_string()).into()),
+    }
+  }
+
+  // Define the functions of a program, then run it as a script in a fresh global frame.
+  fn program(&mut self, children: &[Node], span: Span) -> Result<Value, Unwind> {
+    // Functions can be called from anywhere, so all of them are defined before anything runs. Only this
+    // program's functions can be called, not those of programs the runtime ran before.
+    self.functions.clear();
+    for n in children {
+      if let Node::FunctionDefine { .. } = n {
+        self.run(n)?;
+      }
+    }
+    // Top-level code runs in order in the global frame, which stays at the bottom of the stack
+    // so that every function can see the globals.
+    self.stack.push(Frame { function: String::new(), scopes: vec![HashMap::new()] });
+    let result = self.script(children, span);
+    self.pop_frame();
+    result
+  }
+
+  // Run the body of the first branch whose condition holds.
+  fn if_chain(&mut self, children: &[Node]) -> Result<Value, Unwind> {
+    for branch in children {
+      match branch {
+        Node::IfStatement { children, .. } |
+        Node::ElseIfStatement { children, .. } => {
+          match self.run(&children[0])? {
+            Value::Bool(true) => return self.run(&children[1]),
+            Value::Bool(false) => (),
+            other => return Err(RuntimeErrorKind::NonBooleanCondition(other.type_name()).into()),
+          }
+        },
+        // An `ElseStatement` is always last in the chain, so reaching it means no condition held.
+        Node::ElseStatement { children, .. } => {
+          return self.run(&children[0]);
+        },
+        _ => return Err(RuntimeErrorKind::InvalidNode("if branch").into()),
+      }
+    }
+    // No branch was taken.
+    Ok(Value::Unit)
+  }
+
+  // Run the body for as long as the condition holds.
+  fn while_loop(&mut self, children: &[Node]) -> Result<Value, Unwind> {
+    loop {
+      match self.run(&children[0])? {
+        Value::Bool(true) => (),
+        Value::Bool(false) => break,
+        other => return Err(RuntimeErrorKind::NonBooleanCondition(other.type_name()).into()),
+      }
+      match self.run(&children[1]) {
+        // `continue` just skips the rest of the body, which is over by now.
+        Ok(_) | Err(Unwind::Continue(_)) => (),
+        Err(Unwind::Break(_)) => break,
+        // Returns and errors carry on unwinding past the loop.
+        Err(other) => return Err(other),
+      }
+    }
+    Ok(Value::Unit)
+  }
+
+  // Run the body once for each value the loop steps through.
+  fn for_loop(&mut self, children: &[Node]) -> Result<Value, Unwind> {
+    let variable = match &children[0] {
+      Node::Identifier { value, .. } => value.clone(),
+      _ => return Err(RuntimeErrorKind::InvalidNode("loop variable").into()),
+    };
+    let iteration = self.iteration(&children[1])?;
+    // The loop variable lives in a scope of its own around the body.
+    self.frame().scopes.push(HashMap::new());
+    let mut result = Ok(Value::Unit);
+    for value in iteration {
+      if let Err(unwind) = self.declare(variable.clone(), Binding::variable(value)) {
+        result = Err(unwind);
+        break;
+      }
+      match self.run(&children[2]) {
+        Ok(_) | Err(Unwind::Continue(_)) => (),
+        Err(Unwind::Break(_)) => break,
+        Err(other) => {
+          result = Err(other);
+          break;
+        },
+      }
+    }
+    self.pop_scope();
+    result
+  }
+
+  // Run a block's statements in a scope of their own.
+  fn block(&mut self, children: &[Node]) -> Result<Value, Unwind> {
+    self.frame().scopes.push(HashMap::new());
+    let mut result = Ok(Value::Unit);
+    for n in children {
+      result = self.run(n);
+      if result.is_err() {
+        break;
+      }
+    }
+    // The scope ends however the block was left, including by `return`, `break` or an error.
+    self.pop_scope();
+    result
+  }
+
+  // Update an existing variable, applying the operator of a compound assignment first.
+  fn assignment(&mut self, name: &str, children: &[Node]) -> Result<Value, Unwind> {
+    let variable = match &children[0] {
+      Node::Identifier { value, .. } => value,
+      _ => return Err(RuntimeErrorKind::InvalidNode("variable name").into()),
+    };
+    let value = self.run(&children[1])?;
+    let current = match self.lookup(variable) {
+      Some(Binding { value, mutable: true }) => value.clone(),
+      Some(Binding { mutable: false, .. }) => return Err(RuntimeErrorKind::AssignToConstant(variable.clone()).into()),
+      None => return Err(RuntimeErrorKind::UndefinedVariable(variable.clone()).into()),
+    };
+    // A compound assignment like `+=` applies the operator before the `=` to the old and new values.
+    let value = match name.strip_suffix('=') {
+      Some("") => value,
+      Some(operator) => arithmetic(operator, current, value)?,
+      None => return Err(RuntimeErrorKind::UnknownOperator(name.to_string()).into()),
+    };
+    // Update the binding where it was declared, which may be in an enclosing scope.
+    self.allocate(size_of_value(&value))?;
+    if let Some(binding) = self.lookup(variable) {
+      let old = std::mem::replace(&mut binding.value, value.clone());
+      self.release(size_of_value(&old));
+    }
+    Ok(value)
+  }
+
+  // Compare two numbers, or two booleans for equality.
+  fn comparison(&mut self, name: &str, children: &[Node]) -> Result<Value, Unwind> {
+    let left_value = self.run(&children[0])?;
+    let right_value = self.run(&children[1])?;
+
+    match (&left_value, &right_value) {
+      (Value::Number(left), Value::Number(right)) => {
+        match name {
+          "==" => Ok(Value::Bool(left == right)),
+          "!=" => Ok(Value::Bool(left != right)),
+          "<=" => Ok(Value::Bool(left <= right)),
+          ">=" => Ok(Value::Bool(left >= right)),
+          "<" => Ok(Value::Bool(left < right)),
+          ">" => Ok(Value::Bool(left > right)),
+          _ => Err(RuntimeErrorKind::UnknownOperator(name.to_string()).into()),
+        }
+      },
+      (Value::Bool(left), Value::Bool(right)) if name == "==" || name == "!=" => {
+        Ok(Value::Bool((left == right) == (name == "==")))
+      },
+      // Anything else, including ordering booleans, is a type error.
+      _ => Err(RuntimeErrorKind::TypeMismatch {
+        operator: name.to_string(),
+        left: left_value.type_name(),
+        right: right_value.type_name(),
+      }.into()),
+    }
+  }
+
+  // Combine two booleans with `&&` or `||`, skipping the right operand when the left decides the result.
+  fn logical(&mut self, name: &str, children: &[Node]) -> Result<Value, Unwind> {
+    let invalid = |value: &Value| RuntimeErrorKind::InvalidOperand { operator: name.to_string(), found: value.type_name() };
+    let left = match self.run(&children[0])? {
+      Value::Bool(left) => left,
+      other => return Err(invalid(&other).into()),
+    };
+    match (name, left) {
+      // `false && _` is false and `true || _` is true without looking at the right operand.
+      ("&&", false) => Ok(Value::Bool(false)),
+      ("||", true) => Ok(Value::Bool(true)),
+      ("&&", true) | ("||", false) => {
+        match self.run(&children[1])? {
+          Value::Bool(right) => Ok(Value::Bool(right)),
+          other => Err(invalid(&other).into()),
+        }
+      },
+      _ => Err(RuntimeErrorKind::UnknownOperator(name.to_string()).into()),
+    }
+  }
+
+  // Call the function `name`, passing the arguments in `children`.
+  fn call(&mut self, name: &str, children: &[Node]) -> Result<Value, Unwind> {
+    let (function, arguments) = self.prepare_call(name, children)?;
+    self.invoke(name.to_string(), function, arguments)
+  }
+
+  // Find the function a call refers to and evaluate its arguments, checking that there is one per parameter.
+  fn prepare_call(&mut self, name: &str, children: &[Node]) -> Result<(Rc<Function>, Vec<Value>), Unwind> {
+    // Extract the input arguments.
+    let in_args = match children.first() {
+      Some(Node::FunctionArguments { children, .. }) => children.as_slice(),
+      _ => children,
+    };
+    // Taking a shared handle to the definition instead of borrowing it from `self.functions`
+    // leaves `self` free to run the arguments and statements.
+    let function = match self.functions.get(name) {
+      Some(function) => function.clone(),
+      None => return Err(RuntimeErrorKind::UndefinedFunction(name.to_string()).into()),
+    };
+    // The call has to supply exactly one argument per parameter.
+    let expected = function.parameters.len();
+    if in_args.len() != expected {
+      return Err(RuntimeErrorKind::ArityMismatch { function: name.to_string(), expected, found: in_args.len() }.into());
+    }
+    // Arguments are run in the caller's frame, before the new one is pushed.
+    let mut arguments = Vec::with_capacity(in_args.len());
+    for in_arg in in_args {
+      arguments.push(self.run(in_arg)?);
+    }
+    Ok((function, arguments))
+  }
+
+  // Run the body of `function` in a new frame with its parameters bound to `arguments`. When the body ends
+  // in a tail call, the frame is replaced by one for the next function and the loop goes round again, so
+  // tail recursion does not nest any deeper.
+  fn invoke(&mut self, mut name: String, mut function: Rc<Function>, mut arguments: Vec<Value>) -> Result<Value, Unwind> {
+    // Every frame above the global one is a call in progress. Refuse to nest calls past the limit.
+    if self.stack.len() > self.max_call_depth {
+      let mut calls = self.calls();
+      calls.push(name);
+      return Err(RuntimeErrorKind::StackOverflow { depth: self.max_call_depth, calls }.into());
+    }
+    loop {
+      // Bind the value of each argument to the corresponding parameter in a new frame.
+      let parameters = function.parameters.iter().cloned().zip(arguments.drain(..).map(Binding::variable)).collect();
+      self.allocate(size_of_scope(&parameters))?;
+      self.stack.push(Frame { function: name.clone(), scopes: vec![parameters] });
+      // Evaluate each statement in the function body, stopping early if one of them unwinds.
+      let mut result = Ok(Value::Unit);
+      for n in &function.body {
+        if let Err(unwind) = self.run(n) {
+          result = Err(unwind);
+          break;
+        }
+      }
+      // Pop the frame off the stack.
+      self.pop_frame();
+      let mut result = match result {
+        // A `return` inside the body ends the call with its value.
+        Err(Unwind::Return(value)) => Ok(value),
+        // A `return` of another call carries on as that call.
+        Err(Unwind::TailCall(call)) => {
+          TailCall { name, function, arguments } = *call;
+          continue;
+        },
+        // Loop control that reaches the function boundary was not inside any loop.
+        Err(Unwind::Break(span)) => Err(outside_loop("break", span).into()),
+        Err(Unwind::Continue(span)) => Err(outside_loop("continue", span).into()),
+        other => other,
+      };
+      // An error raised directly in this body is attributed to this function.
+      if let Err(Unwind::Error(ref mut error)) = result {
+        if error.function.is_none() {
+          error.function = Some(name);
+        }
+      }
+      return result;
+    }
+  }
+
+
+  // Run the top-level statements and expressions of a program, or call `main` if there are none.
+  // The result is that of the last top-level item, or else of `main`.
+  fn script(&mut self, children: &[Node], span: Span) -> Result<Value, Unwind> {
+    let mut result = None;
+    for n in children {
+      if let Node::Statement { .. } | Node::Expression { .. } = n {
+        result = Some(self.run(n)?);
+      }
+    }
+    match result {
+      Some(value) => Ok(value),
+      None => self.run(&Node::FunctionCall { name: "main".to_string(), children: vec![], span }),
+    }
+  }
+
+  // The innermost binding of `name` visible from the running code: in the scopes of the current call, and then among the globals.
+  // A function only sees the outermost global scope, not the blocks of whatever top-level code called it.
+  fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+    let (globals, calls) = self.stack.split_first_mut()?;
+    match calls.last_mut() {
+      Some(frame) => frame.lookup(name).or_else(|| globals.scopes.first_mut()?.get_mut(name)),
+      None => globals.lookup(name),
+    }
+  }
+
+  // The error for running out of nesting depth. Each call takes several levels, so recursion usually runs
+  // out of nesting before it reaches the call depth limit. When calls are nested, this is reported as the
+  // stack overflow it is, with the call that was running as the one that went over.
+  fn nesting_too_deep(&self) -> RuntimeErrorKind {
+    let calls = self.calls();
+    match calls.len() {
+      0 | 1 => RuntimeErrorKind::NestingTooDeep { depth: self.max_nesting_depth, calls },
+      nested => RuntimeErrorKind::StackOverflow { depth: nested - 1, calls },
+    }
+  }
+
+  // The functions being called, outermost first.
+  fn calls(&self) -> Vec<String> {
+    self.stack.iter().skip(1).map(|frame| frame.function.clone()).collect()
+  }
+
+  // Bind `name` in the current scope, accounting for the memory the binding holds.
+  fn declare(&mut self, name: String, binding: Binding) -> Result<(), Unwind> {
+    let name_size = name.len();
+    self.allocate(name_size + size_of_value(&binding.value))?;
+    // Redeclaring a name in the same scope drops the binding it replaces.
+    if let Some(old) = self.frame().declare(name, binding) {
+      self.release(name_size + size_of_value(&old.value));
+    }
+    Ok(())
+  }
+
+  // End the innermost scope of the current frame, along with the variables declared in it.
+  fn pop_scope(&mut self) {
+    if let Some(scope) = self.frame().scopes.pop() {
+      self.release(size_of_scope(&scope));
+    }
+  }
+
+  // End the current frame, along with all of its variables.
+  fn pop_frame(&mut self) {
+    if let Some(frame) = self.stack.pop() {
+      let size = frame.scopes.iter().map(size_of_scope).sum();
+      self.release(size);
+    }
+  }
+
+  // Account for `bytes` more memory being held, failing if that goes over the limit.
+  fn allocate(&mut self, bytes: usize) -> Result<(), Unwind> {
+    self.check_temporary(bytes)?;
+    self.memory_used += bytes;
+    Ok(())
+  }
+
+  // Account for `bytes` of memory no longer being held.
+  fn release(&mut self, bytes: usize) {
+    self.memory_used = self.memory_used.saturating_sub(bytes);
+  }
+
+  // Fail if holding `bytes` more memory, even briefly, would go over the limit.
+  fn check_temporary(&self, bytes: usize) -> Result<(), Unwind> {
+    match self.memory_limit {
+      Some(limit) if self.memory_used.saturating_add(bytes) > limit => Err(RuntimeErrorKind::MemoryLimitExceeded { limit }.into()),
+      _ => Ok(()),
+    }
+  }
+
+  // The frame of the function call that is running.
+  fn frame(&mut self) -> &mut Frame {
+    self.stack.last_mut().expect("code only runs inside a function call")
+  }
+
+  // Work out what a `for` loop iterates over: a `Range` node, or an expression that evaluates to a string.
+  fn iteration(&mut self, node: &Node) -> Result<Iteration, Unwind> {
+    match node {
+      Node::Range { inclusive, children, .. } => {
+        let operator = if *inclusive { "..=" } else { ".." };
+        let mut bounds = vec![];
+        for (child, operator) in children.iter().zip([operator, operator, "step"]) {
+          match self.run(child)? {
+            Value::Number(bound) => bounds.push(bound),
+            other => return Err(RuntimeErrorKind::InvalidOperand { operator: operator.to_string(), found: other.type_name() }.into()),
+          }
+        }
+        let step = bounds.get(2).copied().unwrap_or(1);
+        if step == 0 {
+          return Err(RuntimeErrorKind::ZeroStep.into());
+        }
+        Ok(Iteration::Range { next: Some(bounds[0]), end: bounds[1], step, inclusive: *inclusive })
+      },
+      _ => match self.run(node)? {
+        Value::String(value) => {
+          let chars: Vec<char> = value.chars().collect();
+          self.check_temporary(chars.len() * std::mem::size_of::<char>())?;
+          Ok(Iteration::Chars(chars.into_iter()))
+        },
+        other => Err(RuntimeErrorKind::NotIterable(other.type_name()).into()),
+      },
+    }
+  }
+}
+
+pub fn start_interpreter(node: &Node) -> Result<Value, RuntimeError> {
+  Runtime::new().execute(node)
+}
+
+// Apply the arithmetic operator `name` to two values.
+fn arithmetic(name: &str, lhs: Value, rhs: Value) -> Result<Value, Unwind> {
+  match (lhs, rhs) {
+    // If both operands are `Number` values, extract their values and evaluate the expression.
+    (Value::Number(lhs), Value::Number(rhs)) => {
+      // Every operator is checked, so a result that does not fit is `None` rather than wrapping or panicking.
+      let result = match name {
+        // If the operator is `+`, add the values.
+        "+" => lhs.checked_add(rhs),
+        // If the operator is `-`, subtract the values.
+        "-" => lhs.checked_sub(rhs),
+        // If the operator is `*`, multiply the values.
+        "*" => lhs.checked_mul(rhs),
+        // If the operator is `/`, divide the values.
+        "/" if rhs == 0 => return Err(RuntimeErrorKind::DivisionByZero.into()),
+        "/" => lhs.checked_div(rhs),
+        // If the operator is `^`, raise the left value to the power of the right value by repeated squaring.
+        "^" => match u32::try_from(rhs) {
+          Ok(exponent) => lhs.checked_pow(exponent),
+          Err(_) => return Err(RuntimeErrorKind::NegativeExponent.into()),
+        },
+        // If the operator is not recognized, return an error.
+        _ => return Err(RuntimeErrorKind::UnknownOperator(name.to_string()).into()),
+      };
+      result.map(Value::Number).ok_or_else(|| RuntimeErrorKind::Overflow(name.to_string()).into())
+    }
+    // If either operand is not a `Number` value, return an error naming both operand types.
+    (lhs, rhs) => Err(RuntimeErrorKind::TypeMismatch {
+      operator: name.to_string(),
+      left: lhs.type_name(),
+      right: rhs.type_name(),
+    }.into()),
+  }
+}
+
+// Errors are raised without a location; the innermost node they pass through is where they happened.
+fn locate(unwind: Unwind, span: Span) -> Unwind {
+  match unwind {
+    Unwind::Error(mut error) if error.span.is_none() => {
+      error.span = Some(span);
+      Unwind::Error(error)
+    },
+    other => other,
+  }
+}
+
+// Convert the outcome of `run` at the top level into the public result type.
+fn finish(result: Result<Value, Unwind>) -> Result<Value, RuntimeError> {
+  match result {
+    Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+    Err(Unwind::Error(error)) => Err(*error),
+    Err(Unwind::Break(span)) => Err(outside_loop("break", span)),
+    Err(Unwind::Continue(span)) => Err(outside_loop("continue", span)),
+    Err(Unwind::TailCall(_)) => unreachable!("tail calls are only made inside a function call, which runs them"),
+  }
 }
 
-pub fn start_interpreter(node: &Node) -> Result<Value, &'static str> {
-  let mut runtime = Runtime::new();
-  runtime.run(node);
-  let start_main = Node::FunctionCall{name: "main".to_string(), children: vec![]};
-  runtime.run(&start_main)
+// The error for a `break` or `continue` that escaped every loop.
+fn outside_loop(keyword: &'static str, span: Span) -> RuntimeError {
+  RuntimeError { span: Some(span), ..RuntimeError::new(RuntimeErrorKind::OutsideLoop(keyword)) }
 }
\ No newline at end of file
@@ -0,0 +1,170 @@
+// The lexer turns source text into a flat list of tokens, so the parser never has to think about
+// whitespace, comments or how many characters an operator takes up.
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take, take_until},
+    character::complete::{alpha1, alphanumeric1, char, digit1, multispace1, not_line_ending},
+    combinator::{opt, recognize},
+    multi::many0,
+    sequence::{delimited, pair},
+    IResult,
+};
+use nom_locate::LocatedSpan;
+use crate::error::ParseError;
+use std::fmt;
+
+  // The lexer input: the remaining source text, which also knows its offset, line and column in the original.
+  pub type Source<'a> = LocatedSpan<&'a str>;
+
+  // A region of the source text: a byte range plus the line and column (both 1-based) where it starts.
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+  pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub column: usize,
+  }
+  impl Span {
+    // The span of everything consumed between two positions of the input.
+    pub fn between(start: &Source, end: &Source) -> Span {
+      Span {
+        start: start.location_offset(),
+        end: end.location_offset(),
+        line: start.location_line(),
+        column: start.get_utf8_column(),
+      }
+    }
+    // The smallest span covering both `self` and `other`, assuming `self` starts first.
+    pub fn to(self, other: Span) -> Span {
+      Span { end: other.end, ..self }
+    }
+  }
+  impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(f, "{}:{}", self.line, self.column)
+    }
+  }
+
+  // Words that are part of the language and cannot be used as identifiers.
+  pub const KEYWORDS: [&str; 14] = ["fn", "let", "const", "return", "if", "else", "while", "for", "in", "step", "break", "continue", "true", "false"];
+  // Operators, longest first so that `<=` is not read as `<` followed by `=`.
+  pub const OPERATORS: [&str; 21] = ["..=", "..", "==", "!=", "<=", ">=", "&&", "||", "+=", "-=", "*=", "/=", "<", ">", "+", "-", "*", "/", "^", "=", "!"];
+  // Single characters that separate or group other tokens.
+  pub const PUNCTUATION: [&str; 6] = ["(", ")", "{", "}", ",", ";"];
+
+  #[derive(Debug, Clone, PartialEq)]
+  pub enum TokenKind {
+    Identifier(String),
+    Keyword(&'static str),
+    // Up to 2147483648, one more than fits in an i32, so that `-2147483648` can be written.
+    Number(i64),
+    String(String),
+    Operator(&'static str),
+    Punctuation(&'static str),
+    // Marks the end of the source, so the parser always has a position to point at.
+    Eof,
+  }
+
+  #[derive(Debug, Clone, PartialEq)]
+  pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+  }
+
+  // Whitespace and comments, which separate tokens but are otherwise ignored.
+  fn trivia(input: Source) -> IResult<Source, Vec<Source>> {
+    many0(alt((multispace1, line_comment, block_comment)))(input)
+  }
+  // line_comment = "//" (any character except a newline)* ;
+  fn line_comment(input: Source) -> IResult<Source, Source> {
+    recognize(pair(tag("//"), not_line_ending))(input)
+  }
+  // block_comment = "/*" (anything)* "*/" ;
+  fn block_comment(input: Source) -> IResult<Source, Source> {
+    recognize(delimited(tag("/*"), take_until("*/"), tag("*/")))(input)
+  }
+  // A keyword or an identifier: a letter or underscore followed by letters, digits and underscores.
+  fn word(input: Source) -> IResult<Source, TokenKind> {
+    let (input, word) = recognize(pair(alt((alpha1, tag("_"))), many0(alt((alphanumeric1, tag("_"))))))(input)?;
+    let kind = match KEYWORDS.iter().find(|keyword| *keyword == word.fragment()) {
+      Some(keyword) => TokenKind::Keyword(keyword),
+      None => TokenKind::Identifier(word.fragment().to_string()),
+    };
+    Ok((input, kind))
+  }
+  fn number(input: Source) -> IResult<Source, TokenKind> {
+    let (rest, digits) = digit1(input)?;
+    // Literals too large for an i32 are rejected here rather than panicking later. The one exception is the
+    // magnitude of the smallest i32, which the parser accepts after a `-`.
+    match digits.fragment().parse::<i64>() {
+      Ok(number) if number <= i64::from(i32::MAX) + 1 => Ok((rest, TokenKind::Number(number))),
+      _ => Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Digit))),
+    }
+  }
+  fn string(input: Source) -> IResult<Source, TokenKind> {
+    let (input, value) = delimited(char('"'), opt(is_not("\"")), char('"'))(input)?;
+    Ok((input, TokenKind::String(value.map_or("", |value| *value.fragment()).to_string())))
+  }
+  // Match the first entry of `table` that the input starts with.
+  fn symbol<'a>(table: &'static [&'static str], input: Source<'a>) -> IResult<Source<'a>, &'static str> {
+    match table.iter().find(|symbol| input.fragment().starts_with(**symbol)) {
+      Some(symbol) => {
+        let (input, _) = take(symbol.len())(input)?;
+        Ok((input, symbol))
+      },
+      None => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag))),
+    }
+  }
+  fn operator(input: Source) -> IResult<Source, TokenKind> {
+    let (input, operator) = symbol(&OPERATORS, input)?;
+    Ok((input, TokenKind::Operator(operator)))
+  }
+  fn punctuation(input: Source) -> IResult<Source, TokenKind> {
+    let (input, punctuation) = symbol(&PUNCTUATION, input)?;
+    Ok((input, TokenKind::Punctuation(punctuation)))
+  }
+  pub fn token(input: Source) -> IResult<Source, Token> {
+    let start = input;
+    let (input, kind) = alt((word, number, string, operator, punctuation))(input)?;
+    Ok((input, Token { kind, span: Span::between(&start, &input) }))
+  }
+
+  // Split `source` into tokens, ending with an `Eof` token.
+  pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut input = Source::new(source);
+    let mut tokens = vec![];
+    loop {
+      // `trivia` matches zero or more items, so it cannot fail.
+      let (rest, _) = trivia(input).map_err(|_| ParseError::new(source, input.location_offset(), vec![]))?;
+      input = rest;
+      // A comment that never ends would otherwise be read as the operators `/` and `*`.
+      if input.fragment().starts_with("/*") {
+        return Err(lex_error(source, input));
+      }
+      if input.fragment().is_empty() {
+        tokens.push(Token { kind: TokenKind::Eof, span: Span::between(&input, &input) });
+        return Ok(tokens);
+      }
+      match token(input) {
+        Ok((rest, token)) => {
+          tokens.push(token);
+          input = rest;
+        },
+        Err(_) => return Err(lex_error(source, input)),
+      }
+    }
+  }
+
+  // Explain why no token could be read at the start of `input`.
+  fn lex_error(source: &str, input: Source) -> ParseError {
+    let text = input.fragment();
+    if text.starts_with('"') {
+      ParseError::new(source, source.len(), vec!["closing `\"`"])
+    } else if text.starts_with("/*") {
+      ParseError::new(source, source.len(), vec!["`*/` closing the comment"])
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+      ParseError::new(source, input.location_offset(), vec!["number no larger than 2147483647"])
+    } else {
+      ParseError::new(source, input.location_offset(), vec![])
+    }
+  }
@@ -1,7 +1,13 @@
+#![forbid(unsafe_code)]
+
 extern crate nom;
 
+pub mod error;
 pub mod interpreter;
+pub mod lexer;
 pub mod parser;
+pub mod resolver;
 
-pub use self::parser::{program, Node};
-pub use self::interpreter::{start_interpreter, Value};
\ No newline at end of file
+pub use self::parser::{program, Node, Span, MAX_PARSE_DEPTH};
+pub use self::interpreter::{start_interpreter, CancelHandle, Runtime, Value, DEFAULT_MAX_CALL_DEPTH, DEFAULT_MAX_NESTING_DEPTH};
+pub use self::error::{ParseError, RuntimeError, RuntimeErrorKind};
\ No newline at end of file
@@ -1,23 +1,20 @@
-extern crate nom;
 extern crate asalang;
 
-use asalang::{program, start_interpreter, Node};
+use asalang::{program, start_interpreter};
 
-fn main() -> Result<(), nom::Err<(&'static str, nom::error::ErrorKind)>> {
+fn main() {
   
   let result = program(r#"1+true"#);
   match result {
-    Ok((unparsed,tree)) => {
-      println!("Unparsed Text: {:?}", unparsed);
+    Ok(tree) => {
       println!("Parse Tree:\n {:#?}", tree);
-      let result = start_interpreter(&tree);
-      println!("{:?}", result);
+      match start_interpreter(&tree) {
+        Ok(value) => println!("{:?}", value),
+        Err(error) => println!("Runtime Error: {}", error),
+      }
     }
     Err(error) => {
-      println!("ERROR {:?}", error);
+      println!("Parse Error: {}", error);
     }
   }
-
-    
-  Ok(())
 }
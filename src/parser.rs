@@ -3,305 +3,639 @@
 // If you want to use it in your parser, you need to import it here. I've already imported a couple.
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until},
-    character::complete::{alpha1, alphanumeric1, char, digit1, space0, space1},
-    combinator::{map, opt},
-    error::{Error, ErrorKind},
+    combinator::opt,
+    error::{context, ContextError, ErrorKind, ParseError as NomParseError},
     multi::{many0, many1},
-    sequence::{delimited, pair, preceded},
-    IResult,
+    sequence::{delimited, preceded, terminated},
+    IResult, InputLength,
 };
+use crate::error::ParseError;
+use crate::lexer::{tokenize, Token, TokenKind};
+use std::cell::{Cell, RefCell};
+
+pub use crate::lexer::Span;
+
+  // The parser input: the tokens that are left, plus the trackers shared by the whole parse.
+  // The token list always ends with `TokenKind::Eof`, so there is always a current token to look at.
+  #[derive(Debug, Clone, Copy)]
+  pub struct Tokens<'a> {
+    pub tokens: &'a [Token],
+    pub furthest: &'a Furthest,
+    pub nesting: &'a Nesting,
+  }
+  impl<'a> Tokens<'a> {
+    // The byte offset in the source of the next token.
+    pub fn offset(&self) -> usize {
+      self.tokens.first().map_or(0, |token| token.span.start)
+    }
+    fn advance(self, count: usize) -> Tokens<'a> {
+      Tokens { tokens: &self.tokens[count..], ..self }
+    }
+  }
+  impl<'a> InputLength for Tokens<'a> {
+    fn input_len(&self) -> usize {
+      self.tokens.len()
+    }
+  }
+
+  // Remembers the furthest point in the source where a labelled construct was expected but not found,
+  // along with every label expected there. Combinators like `many0` and `alt` throw away the errors of
+  // branches that did not match, so this is kept on the side instead of inside the error values.
+  // `fatal` records a problem that ends the parse outright; it is reported instead of everything else.
+  #[derive(Debug, Default)]
+  pub struct Furthest {
+    offset: Cell<usize>,
+    expected: RefCell<Vec<&'static str>>,
+    fatal: Cell<Option<(usize, &'static str)>>,
+  }
+  impl Furthest {
+    // Stop the whole parse at `input`, reporting that `label` was expected there.
+    fn fail<'a, O>(&self, input: Tokens<'a>, label: &'static str) -> IResult<Tokens<'a>, O, Failure<'a>> {
+      self.fatal.set(Some((input.offset(), label)));
+      Err(nom::Err::Failure(Failure { input }))
+    }
+    fn expect(&self, offset: usize, label: &'static str) {
+      let mut expected = self.expected.borrow_mut();
+      if offset > self.offset.get() || expected.is_empty() {
+        self.offset.set(offset);
+        expected.clear();
+      }
+      if offset == self.offset.get() && !expected.contains(&label) {
+        expected.push(label);
+      }
+    }
+  }
+
+  // How many expressions and blocks can be nested inside each other. Every level takes a good deal of host
+  // stack, so deeper source is rejected rather than crashing the parser.
+  pub const MAX_PARSE_DEPTH: usize = 100;
+
+  // How deeply the expressions and blocks being parsed are nested right now.
+  #[derive(Debug, Default)]
+  pub struct Nesting {
+    depth: Cell<usize>,
+  }
+
+  // Run `parser` one level deeper, failing the whole parse once the nesting limit is reached.
+  fn nested<'a, O>(input: Tokens<'a>, parser: impl FnOnce(Tokens<'a>) -> IResult<Tokens<'a>, O, Failure<'a>>) -> IResult<Tokens<'a>, O, Failure<'a>> {
+    let depth = input.nesting.depth.get();
+    if depth >= MAX_PARSE_DEPTH {
+      return input.furthest.fail(input, "less deeply nested code");
+    }
+    input.nesting.depth.set(depth + 1);
+    let result = parser(input);
+    input.nesting.depth.set(depth);
+    result
+  }
+
+  // The error type threaded through the combinators. It only needs to know where it happened;
+  // what was expected there is recorded in `Furthest` by `context`.
+  #[derive(Debug)]
+  pub struct Failure<'a> {
+    pub input: Tokens<'a>,
+  }
+  impl<'a> NomParseError<Tokens<'a>> for Failure<'a> {
+    fn from_error_kind(input: Tokens<'a>, _kind: ErrorKind) -> Self {
+      Failure { input }
+    }
+    fn append(_input: Tokens<'a>, _kind: ErrorKind, other: Self) -> Self {
+      other
+    }
+    // Of two failed alternatives, the one that got further is the more useful one to report.
+    fn or(self, other: Self) -> Self {
+      if other.input.offset() > self.input.offset() { other } else { self }
+    }
+  }
+  impl<'a> ContextError<Tokens<'a>> for Failure<'a> {
+    // A label only describes the failure if the labelled parser failed without consuming anything;
+    // otherwise something more specific inside it is to blame.
+    fn add_context(input: Tokens<'a>, label: &'static str, other: Self) -> Self {
+      if input.offset() == other.input.offset() {
+        input.furthest.expect(input.offset(), label);
+      }
+      other
+    }
+  }
+
+  // The span from the first token of `start` up to the last token consumed before reaching `end`.
+  fn span_between(start: &Tokens, end: &Tokens) -> Span {
+    let first = start.tokens[0].span;
+    match start.tokens.len() - end.tokens.len() {
+      0 => Span { end: first.start, ..first },
+      consumed => first.to(start.tokens[consumed - 1].span),
+    }
+  }
+
+  // Match a single token of exactly the given kind.
+  fn symbol<'a>(kind: TokenKind) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'a Token, Failure<'a>> {
+    move |input: Tokens<'a>| match input.tokens.first() {
+      Some(token) if token.kind == kind => Ok((input.advance(1), token)),
+      _ => Err(nom::Err::Error(Failure::from_error_kind(input, ErrorKind::Tag))),
+    }
+  }
+  pub fn keyword<'a>(word: &'static str) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'a Token, Failure<'a>> {
+    symbol(TokenKind::Keyword(word))
+  }
+  pub fn operator<'a>(op: &'static str) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'static str, Failure<'a>> {
+    let matches = symbol(TokenKind::Operator(op));
+    move |input: Tokens<'a>| matches(input).map(|(input, _)| (input, op))
+  }
+  pub fn punctuation<'a>(mark: &'static str) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, &'a Token, Failure<'a>> {
+    symbol(TokenKind::Punctuation(mark))
+  }
+  // Match a single token for which `select` returns something, yielding that and the token's span.
+  fn select<'a, T>(select: impl Fn(&'a TokenKind) -> Option<T>) -> impl Fn(Tokens<'a>) -> IResult<Tokens<'a>, (T, Span), Failure<'a>> {
+    move |input: Tokens<'a>| match input.tokens.first().and_then(|token| select(&token.kind).map(|value| (value, token.span))) {
+      Some(selected) => Ok((input.advance(1), selected)),
+      None => Err(nom::Err::Error(Failure::from_error_kind(input, ErrorKind::Tag))),
+    }
+  }
   // Here are the different node types. You will use these to make your parser and your grammar.
   // You may add other nodes as you see fit, but these are expected by the runtime.
   #[derive(Debug, Clone)]
   pub enum Node {
-    Program { children: Vec<Node> },
-    Statement { children: Vec<Node> },
-    FunctionReturn { children: Vec<Node> },
-    FunctionDefine { children: Vec<Node> },
-    FunctionArguments { children: Vec<Node> },
-    FunctionStatements { children: Vec<Node> },
-    Expression { children: Vec<Node> },
-    MathExpression {name: String, children: Vec<Node> },
-    FunctionCall { name: String, children: Vec<Node> },
-    VariableDefine { children: Vec<Node> },
-    Number { value: i32 },
-    Bool { value: bool },
-    Identifier { value: String },
-    String { value: String },
-    ComparisonExpression { name: String, children: Vec<Node> },
-    IfStatement { children: Vec<Node> },
-    ElseStatement { children: Vec<Node> },
-    ElseIfStatement { children: Vec<Node> },
+    Program { children: Vec<Node>, span: Span },
+    Statement { children: Vec<Node>, span: Span },
+    FunctionReturn { children: Vec<Node>, span: Span },
+    FunctionDefine { children: Vec<Node>, span: Span },
+    FunctionArguments { children: Vec<Node>, span: Span },
+    FunctionStatements { children: Vec<Node>, span: Span },
+    Expression { children: Vec<Node>, span: Span },
+    MathExpression { name: String, children: Vec<Node>, span: Span },
+    FunctionCall { name: String, children: Vec<Node>, span: Span },
+    VariableDefine { children: Vec<Node>, span: Span },
+    ConstantDefine { children: Vec<Node>, span: Span },
+    Number { value: i32, span: Span },
+    Bool { value: bool, span: Span },
+    Identifier { value: String, span: Span },
+    String { value: String, span: Span },
+    ComparisonExpression { name: String, children: Vec<Node>, span: Span },
+    IfStatement { children: Vec<Node>, span: Span },
+    ElseStatement { children: Vec<Node>, span: Span },
+    ElseIfStatement { children: Vec<Node>, span: Span },
+    IfChain { children: Vec<Node>, span: Span },
+    Block { children: Vec<Node>, span: Span },
+    LogicalExpression { name: String, children: Vec<Node>, span: Span },
+    UnaryExpression { name: String, children: Vec<Node>, span: Span },
+    Assignment { name: String, children: Vec<Node>, span: Span },
+    WhileLoop { children: Vec<Node>, span: Span },
+    ForLoop { children: Vec<Node>, span: Span },
+    Range { inclusive: bool, children: Vec<Node>, span: Span },
+    Break { span: Span },
+    Continue { span: Span },
+  }
+  impl Node {
+    // Where in the source this node came from.
+    pub fn span(&self) -> Span {
+      match self {
+        Node::Program { span, .. } |
+        Node::Statement { span, .. } |
+        Node::FunctionReturn { span, .. } |
+        Node::FunctionDefine { span, .. } |
+        Node::FunctionArguments { span, .. } |
+        Node::FunctionStatements { span, .. } |
+        Node::Expression { span, .. } |
+        Node::MathExpression { span, .. } |
+        Node::FunctionCall { span, .. } |
+        Node::VariableDefine { span, .. } |
+        Node::ConstantDefine { span, .. } |
+        Node::Number { span, .. } |
+        Node::Bool { span, .. } |
+        Node::Identifier { span, .. } |
+        Node::String { span, .. } |
+        Node::ComparisonExpression { span, .. } |
+        Node::IfStatement { span, .. } |
+        Node::ElseStatement { span, .. } |
+        Node::ElseIfStatement { span, .. } |
+        Node::IfChain { span, .. } |
+        Node::Block { span, .. } |
+        Node::LogicalExpression { span, .. } |
+        Node::UnaryExpression { span, .. } |
+        Node::Assignment { span, .. } |
+        Node::WhileLoop { span, .. } |
+        Node::ForLoop { span, .. } |
+        Node::Range { span, .. } |
+        Node::Break { span } |
+        Node::Continue { span } => *span,
+      }
+    }
+    // The nodes directly beneath this one, in source order.
+    pub fn children(&self) -> &[Node] {
+      match self {
+        Node::Program { children, .. } |
+        Node::Statement { children, .. } |
+        Node::FunctionReturn { children, .. } |
+        Node::FunctionDefine { children, .. } |
+        Node::FunctionArguments { children, .. } |
+        Node::FunctionStatements { children, .. } |
+        Node::Expression { children, .. } |
+        Node::MathExpression { children, .. } |
+        Node::FunctionCall { children, .. } |
+        Node::VariableDefine { children, .. } |
+        Node::ConstantDefine { children, .. } |
+        Node::ComparisonExpression { children, .. } |
+        Node::IfStatement { children, .. } |
+        Node::ElseStatement { children, .. } |
+        Node::ElseIfStatement { children, .. } |
+        Node::IfChain { children, .. } |
+        Node::Block { children, .. } |
+        Node::LogicalExpression { children, .. } |
+        Node::UnaryExpression { children, .. } |
+        Node::Assignment { children, .. } |
+        Node::WhileLoop { children, .. } |
+        Node::ForLoop { children, .. } |
+        Node::Range { children, .. } => children,
+        Node::Number { .. } |
+        Node::Bool { .. } |
+        Node::Identifier { .. } |
+        Node::String { .. } |
+        Node::Break { .. } |
+        Node::Continue { .. } => &[],
+      }
+    }
+  }
+  // The name in an identifier token, along with where it was found.
+  pub fn name(input: Tokens) -> IResult<Tokens, (String, Span), Failure> {
+    select(|kind| match kind {
+      TokenKind::Identifier(name) => Some(name.clone()),
+      _ => None,
+    })(input)
   }
   // Define production rules for an identifier
-  pub fn identifier(input: &str) -> IResult<&str, Node> {
-    let (input, result) = alphanumeric1(input)?;              // Consume at least 1 alphanumeric character. The ? automatically unwraps the result if it's okay and bails if it is an error.
-    Ok((input, Node::Identifier{ value: result.to_string()})) // Return the now partially consumed input, as well as a node with the string on it.
+  pub fn identifier(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, (value, span)) = name(input)?;                // Consume an identifier token. The ? automatically unwraps the result if it's okay and bails if it is an error.
+    Ok((input, Node::Identifier{ value, span}))               // Return the now partially consumed input, as well as a node with the string on it.
   }
   // Define an integer number
-  pub fn number(input: &str) -> IResult<&str, Node> {
-    let (input, result) = digit1(input)?;                     // Consume at least 1 digit 0-9
-    let number = result.parse::<i32>().unwrap();              // Parse the string result into a usize
-    Ok((input, Node::Number{ value: number}))                 // Return the now partially consumed input with a number as well
-  }
-  pub fn boolean(input: &str) -> IResult<&str, Node> {
-    let (input, result) = alt((tag("true"),tag("false")))(input)?;
-    let bool_value = if result == "true" {true} else {false};
-    Ok((input, Node::Bool{ value: bool_value}))
-  }
-  pub fn string(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("\"")(input)?;
-    let (input, string) = many1(alt((alphanumeric1,tag(" "))))(input)?;
-    let (input, _) = tag("\"")(input)?;
-    Ok((input, Node::String{ value: string.join("")}))
-  }
-  pub fn function_call(input: &str) -> IResult<&str, Node> {
-    let (input, name) = alphanumeric1(input)?;
-    let (input, _) = tag("(")(input)?;
-    let (input, mut args) = many0(arguments)(input)?;
-    let (input, _) = tag(")")(input)?;
-    Ok((input, Node::FunctionCall{name: name.to_string(), children: args}))   
-  }
-  pub fn parenthetical_expression(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, _) = tag("(")(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, args) = l1(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, _) = tag(")")(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
+  pub fn number(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (rest, (number, span)) = select(|kind| match kind {   // The lexer has already checked that the digits fit in an i32, apart from 2147483648
+      TokenKind::Number(number) => Some(*number),
+      _ => None,
+    })(input)?;
+    match i32::try_from(number) {
+      Ok(value) => Ok((rest, Node::Number{ value, span})),  // Return the now partially consumed input with a number as well
+      Err(_) => input.furthest.fail(input, "number no larger than 2147483647"),
+    }
+  }
+  // `-2147483648` is read as a single number, since 2147483648 on its own does not fit in an i32. This only
+  // applies when the literal is the whole operand of the `-`, so `-2147483648 ^ 2` is still rejected.
+  fn smallest_number(input: Tokens, precedence: u8) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = operator("-")(input)?;
+    let (input, _) = select(|kind| match kind {
+      TokenKind::Number(number) if *number == -i64::from(i32::MIN) => Some(()),
+      _ => None,
+    })(input)?;
+    match peek_binary_operator(&input) {
+      Some(op) if op.precedence >= precedence => Err(nom::Err::Error(Failure::from_error_kind(start, ErrorKind::Tag))),
+      _ => Ok((input, Node::Number{ value: i32::MIN, span: span_between(&start, &input) })),
+    }
+  }
+  pub fn boolean(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, (value, span)) = select(|kind| match kind {
+      TokenKind::Keyword("true") => Some(true),
+      TokenKind::Keyword("false") => Some(false),
+      _ => None,
+    })(input)?;
+    Ok((input, Node::Bool{ value, span}))
+  }
+  pub fn string(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, (value, span)) = select(|kind| match kind {
+      TokenKind::String(value) => Some(value.clone()),
+      _ => None,
+    })(input)?;
+    Ok((input, Node::String{ value, span}))
+  }
+  pub fn function_call(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, (name, _)) = name(input)?;
+    let (input, _) = punctuation("(")(input)?;
+    let (input, args) = opt(arguments)(input)?;
+    let (input, _) = context("`)` after arguments", punctuation(")"))(input)?;
+    Ok((input, Node::FunctionCall{name, children: args.into_iter().collect(), span: span_between(&start, &input)}))
+  }
+  pub fn parenthetical_expression(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, _) = punctuation("(")(input)?;
+    let (input, args) = context("expression", binary_expression)(input)?;
+    let (input, _) = context("`)`", punctuation(")"))(input)?;
     Ok((input, args))
   }
-  pub fn l4(input: &str) -> IResult<&str, Node> {
-    alt((function_call, number, identifier, parenthetical_expression))(input)
-  }
-  pub fn l3_infix(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, op) = tag("^")(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, args) = l4(input)?;
-    Ok((input, Node::MathExpression{name: op.to_string(), children: vec![args]}))
-  }
-  pub fn l3(input: &str) -> IResult<&str, Node> {
-    let (input, mut head) = l4(input)?;
-    let (input, tail) = many0(l3_infix)(input)?;
-    for n in tail {
-      match n {
-        Node::MathExpression{name, mut children} => {
-          let mut new_children = vec![head.clone()];
-          new_children.append(&mut children);
-          head = Node::MathExpression{name, children: new_children};
-        }
-        _ => () 
-      };
+  // operand = function_call | number | identifier | boolean | string | "(" expression ")" ;
+  pub fn operand(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    alt((function_call, number, identifier, boolean, string, parenthetical_expression))(input)
+  }
+
+  // Which side an operator groups towards when it appears several times in a row.
+  #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+  pub enum Associativity {
+    // `a - b - c` is `(a - b) - c`.
+    Left,
+    // `a ^ b ^ c` is `a ^ (b ^ c)`.
+    Right,
+  }
+
+  // A binary operator the expression parser knows about.
+  pub struct BinaryOperator {
+    pub symbol: &'static str,
+    // Operators with a higher precedence bind more tightly.
+    pub precedence: u8,
+    pub associativity: Associativity,
+    // Builds the node for `left symbol right`.
+    pub build: fn(name: String, children: Vec<Node>, span: Span) -> Node,
+  }
+
+  fn math(name: String, children: Vec<Node>, span: Span) -> Node {
+    Node::MathExpression { name, children, span }
+  }
+  fn comparison(name: String, children: Vec<Node>, span: Span) -> Node {
+    Node::ComparisonExpression { name, children, span }
+  }
+  fn logical(name: String, children: Vec<Node>, span: Span) -> Node {
+    Node::LogicalExpression { name, children, span }
+  }
+
+  // Every binary operator in the language. New operators are added by adding a row here
+  // (and teaching the lexer to produce their token).
+  pub const BINARY_OPERATORS: [BinaryOperator; 13] = [
+    BinaryOperator { symbol: "||", precedence: 10, associativity: Associativity::Left, build: logical },
+    BinaryOperator { symbol: "&&", precedence: 20, associativity: Associativity::Left, build: logical },
+    BinaryOperator { symbol: "==", precedence: 30, associativity: Associativity::Left, build: comparison },
+    BinaryOperator { symbol: "!=", precedence: 30, associativity: Associativity::Left, build: comparison },
+    BinaryOperator { symbol: "<", precedence: 40, associativity: Associativity::Left, build: comparison },
+    BinaryOperator { symbol: ">", precedence: 40, associativity: Associativity::Left, build: comparison },
+    BinaryOperator { symbol: "<=", precedence: 40, associativity: Associativity::Left, build: comparison },
+    BinaryOperator { symbol: ">=", precedence: 40, associativity: Associativity::Left, build: comparison },
+    BinaryOperator { symbol: "+", precedence: 50, associativity: Associativity::Left, build: math },
+    BinaryOperator { symbol: "-", precedence: 50, associativity: Associativity::Left, build: math },
+    BinaryOperator { symbol: "*", precedence: 60, associativity: Associativity::Left, build: math },
+    BinaryOperator { symbol: "/", precedence: 60, associativity: Associativity::Left, build: math },
+    BinaryOperator { symbol: "^", precedence: 70, associativity: Associativity::Right, build: math },
+  ];
+
+  // A prefix operator the expression parser knows about. Its operand only takes binary operators that bind
+  // at least as tightly as `precedence`; everything else applies to the result.
+  pub struct PrefixOperator {
+    pub symbol: &'static str,
+    pub precedence: u8,
+  }
+
+  // Every prefix operator in the language. They build `UnaryExpression` nodes. Both sit between `*` and `^`,
+  // so `-2 * 3` is `(-2) * 3` while `-2 ^ 2` is `-(2 ^ 2)`.
+  pub const PREFIX_OPERATORS: [PrefixOperator; 2] = [
+    PrefixOperator { symbol: "!", precedence: 65 },
+    PrefixOperator { symbol: "-", precedence: 65 },
+  ];
+
+  // The entry in `PREFIX_OPERATORS` for the next token, if it is a prefix operator.
+  fn peek_prefix_operator(input: &Tokens) -> Option<&'static PrefixOperator> {
+    match input.tokens.first().map(|token| &token.kind) {
+      Some(TokenKind::Operator(symbol)) => PREFIX_OPERATORS.iter().find(|op| op.symbol == *symbol),
+      _ => None,
     }
-    Ok((input, head))
-  }
-  pub fn l2_infix(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, op) = alt((tag("*"),tag("/")))(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, args) = l2(input)?;
-    Ok((input, Node::MathExpression{name: op.to_string(), children: vec![args]}))
-  }
-  pub fn l2(input: &str) -> IResult<&str, Node> {
-    let (input, mut head) = l3(input)?;
-    let (input, tail) = many0(l2_infix)(input)?;
-    for n in tail {
-      match n {
-        Node::MathExpression{name, mut children} => {
-          let mut new_children = vec![head.clone()];
-          new_children.append(&mut children);
-          head = Node::MathExpression{name, children: new_children};
+  }
+
+  // unary = prefix_operator unary | operand ;
+  pub fn unary(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    match peek_prefix_operator(&input) {
+      Some(op) => {
+        if op.symbol == "-" {
+          if let Ok(number) = smallest_number(input, op.precedence) {
+            return Ok(number);
+          }
         }
-        _ => () 
-      };
+        let start = input;
+        let (input, operand) = context("expression", |input| climb(input, op.precedence))(input.advance(1))?;
+        let span = span_between(&start, &input);
+        Ok((input, Node::UnaryExpression { name: op.symbol.to_string(), children: vec![operand], span }))
+      },
+      None => operand(input),
     }
-    Ok((input, head))
-  }
-  pub fn l1_infix(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, op) = alt((tag("+"),tag("-")))(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, args) = l2(input)?;
-    Ok((input, Node::MathExpression{name: op.to_string(), children: vec![args]}))
-  }
-  pub fn l1(input: &str) -> IResult<&str, Node> {
-    let (input, mut head) = l2(input)?;
-    let (input, tail) = many0(l1_infix)(input)?;
-    for n in tail {
-      match n {
-        Node::MathExpression{name, mut children} => {
-          let mut new_children = vec![head.clone()];
-          new_children.append(&mut children);
-          head = Node::MathExpression{name, children: new_children};
-        }
-        _ => () 
-      };
+  }
+
+  // The entry in `BINARY_OPERATORS` for the next token, if it is a binary operator.
+  fn peek_binary_operator(input: &Tokens) -> Option<&'static BinaryOperator> {
+    match input.tokens.first().map(|token| &token.kind) {
+      Some(TokenKind::Operator(symbol)) => BINARY_OPERATORS.iter().find(|op| op.symbol == *symbol),
+      _ => None,
     }
-    Ok((input, head))
-  }
-  pub fn math_expression(input: &str) -> IResult<&str, Node> {
-    l1(input)
-  }
-  pub fn expression(input: &str) -> IResult<&str, Node> {
-    let (input, result) = alt((boolean, comparison, math_expression, function_call, number, string, identifier))(input)?;
-    Ok((input, Node::Expression{ children: vec![result]}))   
-  }
-  pub fn statement(input: &str) -> IResult<&str, Node> {
-    let (input, _) = many0(alt((tag(" "),tag("\t"))))(input)?;
-    let (input, result) = alt((variable_define, function_return, else_if_statement, else_statement, if_statement))(input)?;
-    let (input, _) = tag(";")(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, _) = many0(tag("\n"))(input)?;
-    Ok((input, Node::Statement{ children: vec![result]}))   
-  }
-  pub fn function_return(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("return ")(input)?;
-    let (input, return_value) = alt((function_call, expression, identifier))(input)?;
-    Ok((input, Node::FunctionReturn{ children: vec![return_value]}))
-  }
-  pub fn variable_define(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("let ")(input)?;
+  }
+
+  // Parse operands joined by binary operators, respecting the precedence and associativity in `BINARY_OPERATORS`.
+  pub fn binary_expression(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    climb(input, 0)
+  }
+  // Precedence climbing: parse an operand, then keep absorbing operators that bind at least as tightly as
+  // `min_precedence`. The right operand of a left associative operator only takes strictly tighter operators,
+  // which is what makes `8 / 2 / 2` group as `(8 / 2) / 2`.
+  // Every nested expression passes through here, so this is where expression nesting is limited.
+  fn climb(input: Tokens, min_precedence: u8) -> IResult<Tokens, Node, Failure> {
+    nested(input, |input| {
+      let (mut input, mut left) = unary(input)?;
+      while let Some(op) = peek_binary_operator(&input).filter(|op| op.precedence >= min_precedence) {
+        let next_precedence = match op.associativity {
+          Associativity::Left => op.precedence + 1,
+          Associativity::Right => op.precedence,
+        };
+        let (rest, right) = context("expression", |input| climb(input, next_precedence))(input.advance(1))?;
+        let span = left.span().to(right.span());
+        left = (op.build)(op.symbol.to_string(), vec![left, right], span);
+        input = rest;
+      }
+      Ok((input, left))
+    })
+  }
+  pub fn expression(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, result) = binary_expression(input)?;
+    let span = result.span();
+    Ok((input, Node::Expression{ children: vec![result], span}))   
+  }
+  pub fn statement(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let simple = alt((variable_define, constant_define, assignment, function_return, break_statement, continue_statement));
+    let (input, result) = alt((if_chain, while_loop, for_loop, terminated(simple, context("`;` after statement", punctuation(";")))))(input)?;
+    Ok((input, Node::Statement{ children: vec![result], span: span_between(&start, &input)}))   
+  }
+  pub fn function_return(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("return")(input)?;
+    let (input, return_value) = context("expression", expression)(input)?;
+    Ok((input, Node::FunctionReturn{ children: vec![return_value], span: span_between(&start, &input)}))
+  }
+  pub fn variable_define(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("let")(input)?;
+    let (input, variable) = context("variable name", identifier)(input)?;
+    let (input, _) = context("`=`", operator("="))(input)?;
+    let (input, expression) = context("expression", expression)(input)?;
+    Ok((input, Node::VariableDefine{ children: vec![variable, expression], span: span_between(&start, &input)}))   
+  }
+  // constant_define = "const" identifier "=" expression ;
+  pub fn constant_define(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("const")(input)?;
+    let (input, constant) = context("constant name", identifier)(input)?;
+    let (input, _) = context("`=`", operator("="))(input)?;
+    let (input, expression) = context("expression", expression)(input)?;
+    Ok((input, Node::ConstantDefine{ children: vec![constant, expression], span: span_between(&start, &input)}))
+  }
+  // assignment = identifier ("=" | "+=" | "-=" | "*=" | "/=") expression ;
+  pub fn assignment(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
     let (input, variable) = identifier(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, _) = tag("=")(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, expression) = expression(input)?;
-    Ok((input, Node::VariableDefine{ children: vec![variable, expression]}))   
+    let (input, op) = alt((operator("="), operator("+="), operator("-="), operator("*="), operator("/=")))(input)?;
+    let (input, expression) = context("expression", expression)(input)?;
+    Ok((input, Node::Assignment{ name: op.to_string(), children: vec![variable, expression], span: span_between(&start, &input)}))
   }
-  pub fn arguments(input: &str) -> IResult<&str, Node> {
+  pub fn arguments(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
     let (input, arg) = expression(input)?;
     let (input, mut others) = many0(other_arg)(input)?;
     let mut args = vec![arg];
     args.append(&mut others);
-    Ok((input, Node::FunctionArguments{children: args}))
-  }
-  pub fn other_arg(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag(",")(input)?;
-    expression(input)
-  }
-  pub fn function_definition(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("fn ")(input)?;
-    let (input, function_name) = identifier(input)?;
-    let (input, _) = tag("(")(input)?;
-    let (input, mut args) = many0(arguments)(input)?;
-    let (input, _) = tag(")")(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, _) = tag("{")(input)?;
-    let (input, _) = many0(tag("\n"))(input)?;
-    let (input, mut statements) = many1(statement)(input)?;
-    let (input, _) = tag("}")(input)?;
-    let (input, _) = many0(alt((tag("\n"),tag(" "))))(input)?;
+    Ok((input, Node::FunctionArguments{children: args, span: span_between(&start, &input)}))
+  }
+  pub fn other_arg(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, _) = punctuation(",")(input)?;
+    context("expression", expression)(input)
+  }
+  // parameters = identifier ("," identifier)* ;
+  pub fn parameters(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, first) = identifier(input)?;
+    let (input, mut others) = many0(preceded(punctuation(","), context("parameter name", identifier)))(input)?;
+    let mut parameters = vec![first];
+    parameters.append(&mut others);
+    Ok((input, Node::FunctionArguments{children: parameters, span: span_between(&start, &input)}))
+  }
+  pub fn function_definition(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("fn")(input)?;
+    let (input, function_name) = context("function name", identifier)(input)?;
+    let (input, _) = context("`(`", punctuation("("))(input)?;
+    let (input, args) = opt(context("parameter name", parameters))(input)?;
+    let (input, _) = context("`)` after parameters", punctuation(")"))(input)?;
+    let (input, _) = context("`{`", punctuation("{"))(input)?;
+    let (input, mut statements) = many1(context("statement", statement))(input)?;
+    let (input, _) = context("`}`", punctuation("}"))(input)?;
     let mut children = vec![function_name];
-    println!("args, {:?}", args);
-    children.append(&mut args);
+    children.extend(args);
     children.append(&mut statements);
-    Ok((input, Node::FunctionDefine{ children: children }))   
-  }
-  pub fn if_statement(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("if")(input)?;
-    let (input, _) = space1(input)?;
-    let (input, comparison) = comparison(input)?;
-    let (input, _) = space0(input)?;
-    let (input, _) = tag("{")(input)?;
-    let (input, _) = space0(input)?;
-    let (input, statements) = many0(delimited(space0, statement, space0))(input)?;
-    let (input, _) = space0(input)?;
-    let (input, _) = tag("}")(input)?;
+    Ok((input, Node::FunctionDefine{ children, span: span_between(&start, &input) }))   
+  }
+  pub fn if_statement(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("if")(input)?;
+    let (input, comparison) = context("condition", expression)(input)?;
+    let (input, statements) = context("`{`", block)(input)?;
 
-    let children = vec![
-        comparison,
-        Node::Statement {
-            children: statements,
-        },
-    ];
-    Ok((input, Node::IfStatement { children }))
+    let children = vec![comparison, statements];
+    Ok((input, Node::IfStatement { children, span: span_between(&start, &input) }))
 }
-pub fn else_statement(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("else")(input)?;
-    let (input, _) = space0(input)?;
-    let (input, _) = tag("{")(input)?;
-    let (input, _) = space0(input)?;
-    let (input, statements) = many0(delimited(space0, statement, space0))(input)?;
-    let (input, _) = space0(input)?;
-    let (input, _) = tag("}")(input)?;
-    let children = vec![Node::Statement {
-        children: statements,
-    }];
-    Ok((input, Node::ElseStatement { children }))
+// while_loop = "while" expression block ;
+pub fn while_loop(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("while")(input)?;
+    let (input, condition) = context("condition", expression)(input)?;
+    let (input, body) = context("`{`", block)(input)?;
+    Ok((input, Node::WhileLoop { children: vec![condition, body], span: span_between(&start, &input) }))
 }
-pub fn else_if_statement(input: &str) -> IResult<&str, Node> {
-    let (input, _) = tag("else if")(input)?;
-    let (input, _) = space1(input)?;
-    let (input, comparison) = comparison(input)?;
-    let (input, _) = space0(input)?;
-    let (input, _) = tag("{")(input)?;
-    let (input, _) = space0(input)?;
-    let (input, statements) = many0(delimited(space0, statement, space0))(input)?;
-    let (input, _) = space0(input)?;
-    let (input, _) = tag("}")(input)?;
-
-    let children = vec![
-        comparison,
-        Node::Statement {
-            children: statements,
-        },
-    ];
-    Ok((input, Node::ElseIfStatement { children }))
+// for_loop = "for" identifier "in" (range | expression) block ;
+pub fn for_loop(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("for")(input)?;
+    let (input, variable) = context("loop variable", identifier)(input)?;
+    let (input, _) = context("`in`", keyword("in"))(input)?;
+    let (input, iterable) = context("range or expression", alt((range, expression)))(input)?;
+    let (input, body) = context("`{`", block)(input)?;
+    Ok((input, Node::ForLoop { children: vec![variable, iterable, body], span: span_between(&start, &input) }))
 }
-
-// value = number | identifier | boolean;
-pub fn value(input: &str) -> IResult<&str, Node> {
-    alt((boolean, number, identifier))(input).map(|(i, node)| (i.trim_start(), node))
+// range = expression (".." | "..=") expression ("step" expression)? ;
+pub fn range(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, low) = expression(input)?;
+    let (input, op) = alt((operator(".."), operator("..=")))(input)?;
+    let (input, high) = context("expression", expression)(input)?;
+    let (input, step) = opt(preceded(keyword("step"), context("expression", expression)))(input)?;
+    let mut children = vec![low, high];
+    children.extend(step);
+    Ok((input, Node::Range { inclusive: op == "..=", children, span: span_between(&start, &input) }))
 }
-
-
-pub fn comparison(input: &str) -> IResult<&str, Node> {
-    let (input, left) = value(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, operator) = alt((
-        tag("=="), tag("!="), tag("<="), tag(">="), tag("<"), tag(">")
-    ))(input)?;
-    let (input, _) = many0(tag(" "))(input)?;
-    let (input, right) = value(input)?;
-    let children = vec![left, right];
-    let name = match operator {
-        "==" => "==",
-        "!=" => "!=",
-        "<=" => "<=",
-        ">=" => ">=",
-        "<" => "<",
-        ">" => ">",
-        _ => return Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Tag)))
-    };
-    Ok((input, Node::ComparisonExpression { name: name.to_string(), children }))
+pub fn break_statement(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, token) = keyword("break")(input)?;
+    Ok((input, Node::Break { span: token.span }))
+}
+pub fn continue_statement(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let (input, token) = keyword("continue")(input)?;
+    Ok((input, Node::Continue { span: token.span }))
+}
+// block = "{" statement* "}" ;
+pub fn block(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, statements) = nested(input, delimited(punctuation("{"), many0(statement), context("`}`", punctuation("}"))))?;
+    Ok((input, Node::Block { children: statements, span: span_between(&start, &input) }))
 }
+// if_chain = if_statement else_if_statement* else_statement? ;
+pub fn if_chain(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, head) = if_statement(input)?;
+    let (input, mut else_ifs) = many0(else_if_statement)(input)?;
+    let (input, otherwise) = opt(else_statement)(input)?;
+    let mut children = vec![head];
+    children.append(&mut else_ifs);
+    children.extend(otherwise);
+    Ok((input, Node::IfChain { children, span: span_between(&start, &input) }))
+}
+pub fn else_statement(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = keyword("else")(input)?;
+    let (input, statements) = context("`{`", block)(input)?;
+    let children = vec![statements];
+    Ok((input, Node::ElseStatement { children, span: span_between(&start, &input) }))
+}
+pub fn else_if_statement(input: Tokens) -> IResult<Tokens, Node, Failure> {
+    let start = input;
+    let (input, _) = preceded(keyword("else"), keyword("if"))(input)?;
+    let (input, comparison) = context("condition", expression)(input)?;
+    let (input, statements) = context("`{`", block)(input)?;
 
+    let children = vec![comparison, statements];
+    Ok((input, Node::ElseIfStatement { children, span: span_between(&start, &input) }))
+}
 
   // Define a program. You will change this, this is just here for example.
   // You'll probably want to modify this by changing it to be that a program
   // is defined as at least one function definition, but maybe more. Start
   // by looking up the many1() combinator and that should get you started.
-  pub fn program(input: &str) -> IResult<&str, Node> {
-    let (input, result) = many1(alt((function_definition, statement, expression)))(input)?;  // Now that we've defined a number and an identifier, we can compose them using more combinators. Here we use the "alt" combinator to propose a choice.
-    Ok((input, Node::Program{ children: result}))       // Whether the result is an identifier or a number, we attach that to the program
-  }  
-
-
-
-// pub fn comparison_expression(input: &str) -> IResult<&str, Node> {
-//   let (input, left) = simple_expression(input)?;  // parse the left-hand side
-//   let (input, _) = space0(input)?;  // consume any whitespace
-//   let (input, operator) = alt((tag(">"), tag("<"), tag("=="), tag("!="), tag(">="), tag("<=")))(input)?;  // parse the operator
-//   let (input, _) = space0(input)?;  // consume any whitespace
-//   let (input, right) = simple_expression(input)?;  // parse the right-hand side
-
-//   let node = Node::ComparisonExpression {
-//       name: operator.to_string(),
-//       children: vec![left, right],
-//   };
+  pub fn program(source: &str) -> Result<Node, ParseError> {
+    let tokens = tokenize(source)?;
+    let furthest = Furthest::default();
+    let nesting = Nesting::default();
+    let start = Tokens { tokens: &tokens, furthest: &furthest, nesting: &nesting };
+    let item = context("function, statement or expression", alt((function_definition, statement, expression)));
+    let result = many1(item)(start);  // Now that we've defined a number and an identifier, we can compose them using more combinators. Here we use the "alt" combinator to propose a choice.
+    // The whole source has to be consumed; anything left over is where the parse got stuck.
+    let stuck = match result {
+      Ok((rest, children)) if rest.tokens[0].kind == TokenKind::Eof => {
+        let span = span_between(&start, &rest);
+        return Ok(Node::Program{ children, span});                                // Whether the result is an identifier or a number, we attach that to the program
+      },
+      Ok((rest, _)) => rest.offset(),
+      Err(nom::Err::Error(failure)) | Err(nom::Err::Failure(failure)) => failure.input.offset(),
+      Err(nom::Err::Incomplete(_)) => source.len(),
+    };
+    if let Some((offset, label)) = furthest.fatal.get() {
+      return Err(ParseError::new(source, offset, vec![label]));
+    }
+    Err(parse_error(source, &furthest, stuck))
+  }
 
-//   Ok((input, node))
-// }
+  // Build the error for a parse that got stuck at byte `stuck`. If some labelled construct was expected
+  // at or beyond that point, report the furthest such place instead, since it is more specific.
+  fn parse_error(source: &str, furthest: &Furthest, stuck: usize) -> ParseError {
+    let expected = furthest.expected.take();
+    if !expected.is_empty() && furthest.offset.get() >= stuck {
+      ParseError::new(source, furthest.offset.get(), expected)
+    } else {
+      ParseError::new(source, stuck, vec![])
+    }
+  }
@@ -0,0 +1,133 @@
+// The resolver checks a parsed program for mistakes that can be found without running it,
+// so they are reported even when the code containing them would never execute.
+use crate::error::{RuntimeError, RuntimeErrorKind};
+use crate::parser::Node;
+use std::collections::HashMap;
+
+// Check `program` before it runs, returning the first mistake found.
+pub fn resolve(program: &Node) -> Result<(), RuntimeError> {
+  let mut resolver = Resolver { scopes: vec![HashMap::new()], function: None, arities: HashMap::new() };
+  resolver.visit(program)
+}
+
+struct Resolver {
+  // The names declared in each enclosing scope, innermost last, mapped to whether they can be assigned to.
+  scopes: Vec<HashMap<String, bool>>,
+  // The function being checked, so errors can name it the way the runtime does.
+  function: Option<String>,
+  // The number of parameters each function in the program takes.
+  arities: HashMap<String, usize>,
+}
+
+impl Resolver {
+  fn visit(&mut self, node: &Node) -> Result<(), RuntimeError> {
+    match node {
+      // Top-level code is checked first, so that function bodies are checked against every global it declares.
+      Node::Program { children, .. } => {
+        for child in children {
+          if let Node::FunctionDefine { children, .. } = child {
+            if let Node::Identifier { value, .. } = &children[0] {
+              let arity = match children.get(1) {
+                Some(Node::FunctionArguments { children, .. }) => children.len(),
+                _ => 0,
+              };
+              self.arities.insert(value.clone(), arity);
+            }
+          }
+        }
+        let (functions, script): (Vec<&Node>, Vec<&Node>) = children.iter().partition(|child| matches!(child, Node::FunctionDefine { .. }));
+        script.into_iter().chain(functions).try_for_each(|child| self.visit(child))
+      },
+      // A function body starts with only the globals and its parameters in scope. Each parameter needs a
+      // name of its own, or there would be more arguments than bindings.
+      Node::FunctionDefine { children, .. } => {
+        let name = match &children[0] {
+          Node::Identifier { value, .. } => Some(value.clone()),
+          _ => None,
+        };
+        let mut parameters = HashMap::new();
+        for child in children {
+          if let Node::FunctionArguments { children, .. } = child {
+            for parameter in children {
+              if let Node::Identifier { value, span } = parameter {
+                if parameters.insert(value.clone(), true).is_some() {
+                  return Err(RuntimeError {
+                    kind: RuntimeErrorKind::DuplicateParameter(value.clone()),
+                    span: Some(*span),
+                    function: name,
+                  });
+                }
+              }
+            }
+          }
+        }
+        let globals = self.scopes[0].clone();
+        let scopes = std::mem::replace(&mut self.scopes, vec![globals, parameters]);
+        let function = std::mem::replace(&mut self.function, name);
+        let result = children.iter().skip(1).try_for_each(|child| self.visit(child));
+        self.scopes = scopes;
+        self.function = function;
+        result
+      },
+      // A declaration comes into scope after its own initializer.
+      Node::VariableDefine { children, .. } |
+      Node::ConstantDefine { children, .. } => {
+        self.visit(&children[1])?;
+        if let Node::Identifier { value, .. } = &children[0] {
+          let mutable = matches!(node, Node::VariableDefine { .. });
+          self.scopes.last_mut().unwrap().insert(value.clone(), mutable);
+        }
+        Ok(())
+      },
+      // Assigning to the nearest declaration of a name is an error if that declaration is a `const`.
+      Node::Assignment { children, span, .. } => {
+        self.visit(&children[1])?;
+        if let Node::Identifier { value, .. } = &children[0] {
+          if let Some(false) = self.scopes.iter().rev().find_map(|scope| scope.get(value)) {
+            return Err(RuntimeError {
+              kind: RuntimeErrorKind::AssignToConstant(value.clone()),
+              span: Some(*span),
+              function: self.function.clone(),
+            });
+          }
+        }
+        Ok(())
+      },
+      // A call to a known function has to pass one argument per parameter. Calls to unknown
+      // functions are left for the runtime to report, since they may never happen.
+      Node::FunctionCall { name, children, span } => {
+        let found = match children.first() {
+          Some(Node::FunctionArguments { children, .. }) => children.len(),
+          _ => 0,
+        };
+        match self.arities.get(name) {
+          Some(&expected) if expected != found => Err(RuntimeError {
+            kind: RuntimeErrorKind::ArityMismatch { function: name.clone(), expected, found },
+            span: Some(*span),
+            function: self.function.clone(),
+          }),
+          _ => children.iter().try_for_each(|child| self.visit(child)),
+        }
+      },
+      // The loop variable is declared in a scope of its own around the body.
+      Node::ForLoop { children, .. } => {
+        self.visit(&children[1])?;
+        let mut scope = HashMap::new();
+        if let Node::Identifier { value, .. } = &children[0] {
+          scope.insert(value.clone(), true);
+        }
+        self.scoped(scope, &children[2..])
+      },
+      Node::Block { children, .. } => self.scoped(HashMap::new(), children),
+      _ => node.children().iter().try_for_each(|child| self.visit(child)),
+    }
+  }
+
+  // Visit `nodes` inside a new innermost scope that starts out holding `scope`.
+  fn scoped(&mut self, scope: HashMap<String, bool>, nodes: &[Node]) -> Result<(), RuntimeError> {
+    self.scopes.push(scope);
+    let result = nodes.iter().try_for_each(|node| self.visit(node));
+    self.scopes.pop();
+    result
+  }
+}
@@ -1,33 +1,61 @@
 extern crate asalang;
 extern crate nom;
 
-use asalang::{program, Node, Value, start_interpreter};
-use nom::IResult;
+use asalang::{program, Node, Span, Value, ParseError, RuntimeError, RuntimeErrorKind, Runtime, CancelHandle, start_interpreter};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::time::Duration;
+
+// Counts the heap allocations made by each thread, so a test can tell how much a run copies.
+struct CountingAllocator;
+
+thread_local! {
+  static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.with(|count| count.set(count.get() + 1));
+    System.alloc(layout)
+  }
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// How many heap allocations running `f` on this thread makes.
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+  let before = ALLOCATIONS.with(Cell::get);
+  let result = f();
+  (result, ALLOCATIONS.with(Cell::get) - before)
+}
 
 macro_rules! test {
   ($func:ident, $test:tt, $expected:expr) => (
     #[test]
     fn $func() -> Result<(),String> {
       match program($test) {
-        Ok((input, p)) => {
-          assert_eq!(input, "");
-          assert_eq!(start_interpreter(&p), $expected);
+        Ok(p) => {
+          assert_eq!(start_interpreter(&p).map_err(|e| e.kind), $expected);
           Ok(())
         },
-        Err(e) => Err(format!("{:?}",e)),
+        Err(e) => Err(format!("{}",e)),
       }
     }
   )
 }
 
 test!(numeric, r#"123"#, Ok(Value::Number(123)));
-test!(identifier, r#"x"#, Err("Undefined variable"));
+test!(identifier, r#"x"#, Err(RuntimeErrorKind::UndefinedVariable("x".to_string())));
 test!(string, r#""hello world""#, Ok(Value::String("hello world".to_string())));
 test!(bool_true, r#"true"#, Ok(Value::Bool(true)));
 test!(bool_false, r#"false"#, Ok(Value::Bool(false)));
-test!(function_call, r#"foo()"#, Err("Undefined function"));
-test!(function_call_one_arg, r#"foo(a)"#, Err("Undefined function"));
-test!(function_call_more_args, r#"foo(a,b,c)"#, Err("Undefined function"));
+test!(function_call, r#"foo()"#, Err(RuntimeErrorKind::UndefinedFunction("foo".to_string())));
+test!(function_call_one_arg, r#"foo(a)"#, Err(RuntimeErrorKind::UndefinedFunction("foo".to_string())));
+test!(function_call_more_args, r#"foo(a,b,c)"#, Err(RuntimeErrorKind::UndefinedFunction("foo".to_string())));
 test!(variable_define, r#"let x = 123;"#, Ok(Value::Number(123)));
 test!(variable_init, r#"let x = 1;"#, Ok(Value::Number(1)));
 test!(variable_bool, r#"let bool = true;"#, Ok(Value::Bool(true)));
@@ -41,9 +69,14 @@ test!(math_divide, r#"6 / 2"#, Ok(Value::Number(3)));
 test!(math_exponent, r#"2 ^ 4"#, Ok(Value::Number(16)));
 test!(math_more_terms, r#"10 + 2*6"#, Ok(Value::Number(22)));
 test!(math_more_terms_paren, r#"((10+2)*6)/4"#, Ok(Value::Number(18)));
+test!(math_divide_left_associative, r#"8 / 2 / 2"#, Ok(Value::Number(2)));
+test!(math_subtract_left_associative, r#"10 - 3 - 2"#, Ok(Value::Number(5)));
+test!(math_exponent_right_associative, r#"2 ^ 3 ^ 2"#, Ok(Value::Number(512)));
+test!(math_precedence_ladder, r#"2 + 3 * 4 ^ 2 - 6 / 3"#, Ok(Value::Number(48)));
+test!(comparison_binds_looser_than_math, r#"1 + 2 * 3 == 7"#, Ok(Value::Bool(true)));
 test!(assign_math, r#"let x = 1 + 1;"#, Ok(Value::Number(2)));
-test!(assign_function, r#"let x = foo();"#, Err("Undefined function"));
-test!(assign_function_arguments, r#"let x = foo(a,b,c);"#, Err("Undefined function"));
+test!(assign_function, r#"let x = foo();"#, Err(RuntimeErrorKind::UndefinedFunction("foo".to_string())));
+test!(assign_function_arguments, r#"let x = foo(a,b,c);"#, Err(RuntimeErrorKind::UndefinedFunction("foo".to_string())));
 test!(define_function, r#"fn main(){return foo();} fn foo(){return 5;}"#, Ok(Value::Number(5)));
 test!(define_function_args, r#"fn main(){return foo(1,2,3);} fn foo(a,b,c){return a+b+c;}"#, Ok(Value::Number(6)));
 test!(define_function_more_statement, r#"fn main() {
@@ -66,7 +99,19 @@ fn bar(a) {
 fn main() {
   return foo(1,2,3);  
 }"#, Ok(Value::Number(6)));
-//test!(if_less_than, r#"if 2 < 3 { return "2 is less than 3"; } else { return "2 is not less than 3"; }"#, Ok(Value::String("2 is less than 3".to_string())));
+test!(if_less_than, r#"if 2 < 3 { return "2 is less than 3"; } else { return "2 is not less than 3"; }"#, Ok(Value::String("2 is less than 3".to_string())));
+test!(if_else_taken, r#"if 3 < 2 { return 1; } else { return 2; }"#, Ok(Value::Number(2)));
+test!(if_else_if_chain, r#"fn main() {
+  let x = 5;
+  if x < 3 {
+    return 1;
+  } else if x < 10 {
+    return 2;
+  } else {
+    return 3;
+  }
+}"#, Ok(Value::Number(2)));
+test!(if_no_branch_taken, r#"if 3 < 2 { return 1; }"#, Ok(Value::Unit));
 //-------Comparison Tests-------
 test!(comparison_main, r#"fn main() { return 2 < 3; }"#, Ok(Value::Bool(true)));
 test!(comparison_less_than_equal, r#"2 <= 3"#, Ok(Value::Bool(true)));
@@ -74,8 +119,1128 @@ test!(comparison_greater_than, r#"2 > 3"#, Ok(Value::Bool(false)));
 test!(comparison_greater_than_equal, r#"2 >= 3"#, Ok(Value::Bool(false)));
 //let result = x + y * z > x * y - z == true;
 test!(comparison_main_set_variable, r#"fn main() { let x = 10; let y = 5; let z = 3; return x + y + z;}"#, Ok(Value::Number(18)));
-test!(invalidComparison, r#"1 > true"#, Err("Invalid comparison operands"));
-test!(invalidComparison2, r#"x + y * z > x * y - z == false"#, Ok(Value::Bool(true)));
-// test!(invalidComparison2, r#"5 - false"#, Err("Invalid comparison operands"));
-//problems start here
-//test!(comparison_main_set_variable2, r#"fn main() { let x = 10; let y = 5; let z = 3; let result = x + y * z > x * y - z == true;}"#, Ok(Value::Bool(true)));
\ No newline at end of file
+test!(invalid_comparison, r#"1 > true"#, Err(RuntimeErrorKind::TypeMismatch { operator: ">".to_string(), left: "number", right: "bool" }));
+test!(invalid_comparison2, r#"x + y * z > x * y - z == false"#, Err(RuntimeErrorKind::UndefinedVariable("x".to_string())));
+// test!(invalid_comparison2, r#"5 - false"#, Err("Invalid comparison operands"));
+test!(comparison_main_set_variable2, r#"fn main() { let x = 10; let y = 5; let z = 3; let result = x + y * z > x * y - z == true; return result; }"#, Ok(Value::Bool(false)));
+test!(comparison_chained_with_equality, r#"fn main() { let x = 10; let y = 5; let z = 3; return x + y * z > x * y - z == false; }"#, Ok(Value::Bool(true)));
+test!(comparison_function_call_operand, r#"fn main() { let x = 3; return foo(1) < x + 1; } fn foo(a) { return a * 2; }"#, Ok(Value::Bool(true)));
+test!(comparison_parenthesized_equality, r#"fn main() { let a = 2; let b = 1; return (a > b) == true; }"#, Ok(Value::Bool(true)));
+test!(comparison_as_argument, r#"fn main() { return same(1 < 2, 3 >= 3); } fn same(a, b) { return a == b; }"#, Ok(Value::Bool(true)));
+test!(comparison_in_let, r#"let big = 2 * 3 > 5;"#, Ok(Value::Bool(true)));
+test!(comparison_in_condition, r#"fn main() {
+  if double(2) + 1 == 5 {
+    return "yes";
+  }
+  return "no";
+}
+fn double(n) { return n * 2; }"#, Ok(Value::String("yes".to_string())));
+test!(comparison_bool_ordering_rejected, r#"(1 < 2) < true"#, Err(RuntimeErrorKind::TypeMismatch { operator: "<".to_string(), left: "bool", right: "bool" }));
+//-------Return Tests-------
+test!(return_stops_function, r#"fn main() { return 1; let x = 2; }"#, Ok(Value::Number(1)));
+test!(return_not_overridden_by_let, r#"fn main() { let x = 1; return x; let y = 2; }"#, Ok(Value::Number(1)));
+test!(return_from_nested_if, r#"fn main() {
+  let x = foo(1);
+  return x;
+}
+fn foo(a) {
+  if a < 2 {
+    return 10;
+  }
+  return 20;
+}"#, Ok(Value::Number(10)));
+test!(function_falls_off_end, r#"fn main() { let x = 1; }"#, Ok(Value::Unit));
+//-------Error Tests-------
+test!(math_type_mismatch, r#"fn main() { let s = "hi"; return s * 2; }"#, Err(RuntimeErrorKind::TypeMismatch { operator: "*".to_string(), left: "string", right: "number" }));
+test!(undefined_variable_in_function, r#"fn main() { return foo(1); } fn foo(a) { return b; }"#, Err(RuntimeErrorKind::UndefinedVariable("b".to_string())));
+
+#[test]
+fn runtime_error_names_function() {
+  let p = program(r#"fn main() { return foo(1); } fn foo(a) { return b; }"#).unwrap();
+  let error: RuntimeError = start_interpreter(&p).unwrap_err();
+  assert_eq!(error.function, Some("foo".to_string()));
+  assert_eq!(error.span, Some(Span { start: 48, end: 49, line: 1, column: 49 }));
+  assert_eq!(error.to_string(), "undefined variable `b` at 1:49 in function `foo`");
+}
+//-------Span Tests-------
+#[test]
+fn spans_track_lines_and_columns() {
+  let p = program("fn main() {\n  let x = 12;\n  return x;\n}").unwrap();
+  assert_eq!(p.span(), Span { start: 0, end: 39, line: 1, column: 1 });
+  let Node::Program { children, .. } = &p else { panic!("expected a program") };
+  let Node::FunctionDefine { children, .. } = &children[0] else { panic!("expected a function") };
+  let Node::Statement { children, span } = &children[1] else { panic!("expected a statement") };
+  assert_eq!(*span, Span { start: 14, end: 25, line: 2, column: 3 });
+  let Node::VariableDefine { children, .. } = &children[0] else { panic!("expected a let") };
+  assert_eq!(children[1].span(), Span { start: 22, end: 24, line: 2, column: 11 });
+}
+//-------Parse Error Tests-------
+#[test]
+fn parse_error_missing_semicolon() {
+  let error: ParseError = program("fn main() {\n  let x = 1\n  return x;\n}").unwrap_err();
+  assert_eq!((error.line, error.column), (3, 3));
+  assert!(error.expected.contains(&"`;` after statement"));
+  assert_eq!(error.source_line, "  return x;");
+}
+
+#[test]
+fn parse_error_snippet() {
+  let error = program("let x = ;").unwrap_err();
+  assert_eq!(error.to_string(), "expected expression at 1:9\n1 | let x = ;\n  |         ^");
+}
+
+#[test]
+fn parse_error_unconsumed_input() {
+  let error = program("fn main() { return 1; } }").unwrap_err();
+  assert_eq!((error.line, error.column), (1, 25));
+}
+//-------Lexer Tests-------
+test!(layout_call_spaces, r#"fn main() { return foo( 1 , 2 ); } fn foo(a, b) { return a + b; }"#, Ok(Value::Number(3)));
+test!(layout_tabs_and_newlines, "fn main() {\n\treturn foo(\n\t\t1,\n\t\t2\n\t)\t*\t2;\n}\nfn foo(a,\n b) { return a + b; }", Ok(Value::Number(6)));
+test!(layout_comments, r#"// leading comment
+fn main() {
+  /* block
+     comment */
+  return 1; // trailing comment
+}"#, Ok(Value::Number(1)));
+test!(string_with_punctuation, r#""hello, world!""#, Ok(Value::String("hello, world!".to_string())));
+
+#[test]
+fn tokenize_kinds_and_spans() {
+  use asalang::lexer::{tokenize, TokenKind};
+  let tokens = tokenize("let x = \"a\";\n  foo(12)").unwrap();
+  let kinds: Vec<TokenKind> = tokens.iter().map(|token| token.kind.clone()).collect();
+  assert_eq!(kinds, vec![
+    TokenKind::Keyword("let"),
+    TokenKind::Identifier("x".to_string()),
+    TokenKind::Operator("="),
+    TokenKind::String("a".to_string()),
+    TokenKind::Punctuation(";"),
+    TokenKind::Identifier("foo".to_string()),
+    TokenKind::Punctuation("("),
+    TokenKind::Number(12),
+    TokenKind::Punctuation(")"),
+    TokenKind::Eof,
+  ]);
+  assert_eq!(tokens[7].span, Span { start: 19, end: 21, line: 2, column: 7 });
+}
+
+#[test]
+fn tokenize_errors() {
+  use asalang::lexer::tokenize;
+  assert_eq!(tokenize("let s = \"open").unwrap_err().expected, vec!["closing `\"`"]);
+  assert_eq!(tokenize("99999999999").unwrap_err().expected, vec!["number no larger than 2147483647"]);
+  assert_eq!(tokenize("let x = 1 @ 2;").unwrap_err().column, 11);
+}
+//-------Logical Tests-------
+test!(logical_and, r#"true && false"#, Ok(Value::Bool(false)));
+test!(logical_or, r#"false || true"#, Ok(Value::Bool(true)));
+test!(logical_not, r#"!(1 < 2)"#, Ok(Value::Bool(false)));
+test!(logical_not_binds_tighter_than_equality, r#"!true == false"#, Ok(Value::Bool(true)));
+test!(logical_and_binds_tighter_than_or, r#"true || false && false"#, Ok(Value::Bool(true)));
+test!(logical_below_comparison, r#"1 < 2 && 3 > 4 || 2 + 2 == 4"#, Ok(Value::Bool(true)));
+test!(logical_and_short_circuits, r#"fn main() { return false && missing; }"#, Ok(Value::Bool(false)));
+test!(logical_or_short_circuits, r#"fn main() { return true || foo(); }"#, Ok(Value::Bool(true)));
+test!(logical_right_operand_evaluated, r#"fn main() { return true && foo(); }"#, Err(RuntimeErrorKind::UndefinedFunction("foo".to_string())));
+test!(logical_in_condition, r#"fn main() {
+  let x = 5;
+  if x > 1 && !(x > 10) {
+    return "in range";
+  }
+  return "out of range";
+}"#, Ok(Value::String("in range".to_string())));
+test!(logical_non_bool_operand, r#"1 && true"#, Err(RuntimeErrorKind::InvalidOperand { operator: "&&".to_string(), found: "number" }));
+test!(logical_not_non_bool_operand, r#"!1"#, Err(RuntimeErrorKind::InvalidOperand { operator: "!".to_string(), found: "number" }));
+//-------Negation Tests-------
+test!(negative_literal, r#"-5"#, Ok(Value::Number(-5)));
+test!(negate_times_negative, r#"fn main() { let x = 4; return x * -1; }"#, Ok(Value::Number(-4)));
+test!(subtract_negative, r#"0 - -3"#, Ok(Value::Number(3)));
+test!(double_negation, r#"--3"#, Ok(Value::Number(3)));
+test!(negation_binds_tighter_than_multiply, r#"-2 * 3 + 1"#, Ok(Value::Number(-5)));
+test!(negation_binds_looser_than_exponent, r#"-2 ^ 2"#, Ok(Value::Number(-4)));
+test!(negate_parenthetical, r#"-(1 + 2)"#, Ok(Value::Number(-3)));
+test!(negative_round_trip, r#"fn main() { let x = 3 - 8; let y = -5; return x == y; }"#, Ok(Value::Bool(true)));
+test!(negate_non_number, r#"-true"#, Err(RuntimeErrorKind::InvalidOperand { operator: "-".to_string(), found: "bool" }));
+//-------While Tests-------
+test!(while_sum, r#"fn main() {
+  let i = 0;
+  let sum = 0;
+  while i < 5 {
+    i = i + 1;
+    sum += i;
+  }
+  return sum;
+}"#, Ok(Value::Number(15)));
+test!(while_break, r#"fn main() {
+  let i = 0;
+  while true {
+    if i == 3 {
+      break;
+    }
+    i += 1;
+  }
+  return i;
+}"#, Ok(Value::Number(3)));
+test!(while_continue, r#"fn main() {
+  let i = 0;
+  let sum = 0;
+  while i < 6 {
+    i += 1;
+    if i == 2 || i == 4 || i == 6 {
+      continue;
+    }
+    sum += i;
+  }
+  return sum;
+}"#, Ok(Value::Number(9)));
+test!(while_return_from_body, r#"fn main() {
+  let i = 0;
+  while true {
+    i += 1;
+    if i == 4 {
+      return i * 10;
+    }
+  }
+  return 0;
+}"#, Ok(Value::Number(40)));
+test!(while_nested_break, r#"fn main() {
+  let outer = 0;
+  let count = 0;
+  while outer < 3 {
+    outer += 1;
+    while true {
+      count += 1;
+      break;
+    }
+  }
+  return count;
+}"#, Ok(Value::Number(3)));
+test!(while_condition_false, r#"fn main() { while false { return 1; } return 2; }"#, Ok(Value::Number(2)));
+test!(while_non_bool_condition, r#"fn main() { while 1 { break; } return 0; }"#, Err(RuntimeErrorKind::NonBooleanCondition("number")));
+test!(break_outside_loop, r#"fn main() { break; }"#, Err(RuntimeErrorKind::OutsideLoop("break")));
+test!(continue_outside_loop, r#"fn main() {
+  if true {
+    continue;
+  }
+  return 0;
+}"#, Err(RuntimeErrorKind::OutsideLoop("continue")));
+test!(break_does_not_cross_functions, r#"fn stop() { break; }
+fn main() {
+  while true {
+    let x = stop();
+  }
+  return 0;
+}"#, Err(RuntimeErrorKind::OutsideLoop("break")));
+//-------For Tests-------
+test!(for_exclusive_range, r#"fn main() {
+  let sum = 0;
+  for i in 0..5 {
+    sum += i;
+  }
+  return sum;
+}"#, Ok(Value::Number(10)));
+test!(for_inclusive_range, r#"fn main() {
+  let sum = 0;
+  for i in 1..=5 {
+    sum += i;
+  }
+  return sum;
+}"#, Ok(Value::Number(15)));
+test!(for_step, r#"fn main() {
+  let sum = 0;
+  for i in 0..10 step 3 {
+    sum += i;
+  }
+  return sum;
+}"#, Ok(Value::Number(18)));
+test!(for_negative_step, r#"fn main() {
+  let last = 0;
+  let count = 0;
+  for i in 10..=0 step -5 {
+    last = i;
+    count += 1;
+  }
+  return last * 10 + count;
+}"#, Ok(Value::Number(3)));
+test!(for_empty_range, r#"fn main() {
+  for i in 5..5 {
+    return 1;
+  }
+  return 0;
+}"#, Ok(Value::Number(0)));
+test!(for_range_expressions, r#"fn main() {
+  let n = 3;
+  let count = 0;
+  for i in n - 1..n * 2 {
+    count += 1;
+  }
+  return count;
+}"#, Ok(Value::Number(4)));
+test!(for_range_at_max, r#"fn main() {
+  let count = 0;
+  for i in 2147483646..=2147483647 {
+    count += 1;
+  }
+  return count;
+}"#, Ok(Value::Number(2)));
+test!(for_string_characters, r#"fn main() {
+  let count = 0;
+  for c in "héllo" {
+    count += 1;
+  }
+  return count;
+}"#, Ok(Value::Number(5)));
+test!(for_break_continue, r#"fn main() {
+  let sum = 0;
+  for i in 0..100 {
+    if i == 5 {
+      break;
+    }
+    if i == 2 {
+      continue;
+    }
+    sum += i;
+  }
+  return sum;
+}"#, Ok(Value::Number(8)));
+test!(for_variable_scoped_to_loop, r#"fn main() {
+  for i in 0..3 {
+  }
+  return i;
+}"#, Err(RuntimeErrorKind::UndefinedVariable("i".to_string())));
+test!(for_variable_restores_shadowed, r#"fn main() {
+  let i = 42;
+  for i in 0..3 {
+  }
+  return i;
+}"#, Ok(Value::Number(42)));
+test!(for_zero_step, r#"fn main() { for i in 0..3 step 0 { } return 0; }"#, Err(RuntimeErrorKind::ZeroStep));
+test!(for_non_number_bound, r#"fn main() { for i in 0..true { } return 0; }"#, Err(RuntimeErrorKind::InvalidOperand { operator: "..".to_string(), found: "bool" }));
+test!(for_not_iterable, r#"fn main() { for i in 3 { } return 0; }"#, Err(RuntimeErrorKind::NotIterable("number")));
+
+#[test]
+fn for_missing_in() {
+  let error = program("fn main() { for i 0..3 { } }").unwrap_err();
+  assert_eq!(error.expected, vec!["`in`"]);
+}
+//-------Assignment Tests-------
+test!(assign, r#"fn main() { let x = 1; x = 2; return x; }"#, Ok(Value::Number(2)));
+test!(assign_uses_old_value, r#"fn main() { let x = 5; x = x * x - 1; return x; }"#, Ok(Value::Number(24)));
+test!(assign_changes_type, r#"fn main() { let x = 1; x = "one"; return x; }"#, Ok(Value::String("one".to_string())));
+test!(assign_undeclared, r#"fn main() { x = 2; return x; }"#, Err(RuntimeErrorKind::UndefinedVariable("x".to_string())));
+test!(assign_in_if_body, r#"fn main() {
+  let x = 1;
+  if true {
+    x = 10;
+  }
+  return x;
+}"#, Ok(Value::Number(10)));
+test!(compound_assign, r#"fn main() {
+  let x = 10;
+  x += 5;
+  x -= 3;
+  x *= 4;
+  x /= 6;
+  return x;
+}"#, Ok(Value::Number(8)));
+test!(compound_assign_expression, r#"fn main() { let x = 2; x *= 1 + 2; return x; }"#, Ok(Value::Number(6)));
+test!(compound_assign_undeclared, r#"fn main() { y += 1; return y; }"#, Err(RuntimeErrorKind::UndefinedVariable("y".to_string())));
+test!(compound_assign_type_mismatch, r#"fn main() { let s = "a"; s += 1; return s; }"#, Err(RuntimeErrorKind::TypeMismatch { operator: "+".to_string(), left: "string", right: "number" }));
+test!(let_shadows, r#"fn main() { let x = 1; let x = x + 1; return x; }"#, Ok(Value::Number(2)));
+test!(assign_parameter, r#"fn double(n) { n *= 2; return n; }
+fn main() { let n = 3; let d = double(n); return n * 10 + d; }"#, Ok(Value::Number(36)));
+
+#[test]
+fn assign_missing_expression() {
+  let error = program("fn main() { let x = 1; x += ; }").unwrap_err();
+  assert_eq!(error.expected, vec!["expression"]);
+}
+//-------Constant Tests-------
+test!(const_read, r#"fn main() { const limit = 10; return limit * 2; }"#, Ok(Value::Number(20)));
+test!(const_assign, r#"fn main() { const limit = 10; limit = 11; return limit; }"#, Err(RuntimeErrorKind::AssignToConstant("limit".to_string())));
+test!(const_compound_assign, r#"fn main() { const limit = 10; limit += 1; return limit; }"#, Err(RuntimeErrorKind::AssignToConstant("limit".to_string())));
+test!(const_assign_in_untaken_branch, r#"fn main() {
+  const limit = 10;
+  if false {
+    limit = 0;
+  }
+  return limit;
+}"#, Err(RuntimeErrorKind::AssignToConstant("limit".to_string())));
+test!(const_assign_in_loop, r#"fn main() {
+  const total = 0;
+  for i in 0..3 {
+    total += i;
+  }
+  return total;
+}"#, Err(RuntimeErrorKind::AssignToConstant("total".to_string())));
+test!(const_shadowed_by_let, r#"fn main() { const x = 1; let x = x + 1; x = x * 10; return x; }"#, Ok(Value::Number(20)));
+test!(let_shadowed_by_const, r#"fn main() { let x = 1; const x = 2; x = 3; return x; }"#, Err(RuntimeErrorKind::AssignToConstant("x".to_string())));
+test!(const_is_per_function, r#"fn set(x) { x = 5; return x; }
+fn main() { const x = 1; return set(x) + x; }"#, Ok(Value::Number(6)));
+
+#[test]
+fn const_error_found_before_running() {
+  let p = program(r#"fn main() {
+  const x = 1;
+  return x;
+}
+fn never() {
+  const y = 2;
+  y = 3;
+  return y;
+}"#).unwrap();
+  let error = start_interpreter(&p).unwrap_err();
+  assert_eq!(error.kind, RuntimeErrorKind::AssignToConstant("y".to_string()));
+  assert_eq!(error.to_string(), "cannot assign to constant `y` at 7:3 in function `never`");
+}
+//-------Scope Tests-------
+test!(let_in_if_body_not_visible_after, r#"fn main() {
+  if true {
+    let inner = 1;
+  }
+  return inner;
+}"#, Err(RuntimeErrorKind::UndefinedVariable("inner".to_string())));
+test!(let_in_block_shadows_outer, r#"fn main() {
+  let x = 1;
+  if true {
+    let x = 2;
+  }
+  return x;
+}"#, Ok(Value::Number(1)));
+test!(block_reads_enclosing_scopes, r#"fn main() {
+  let x = 1;
+  if true {
+    let y = 2;
+    if true {
+      return x + y;
+    }
+  }
+  return 0;
+}"#, Ok(Value::Number(3)));
+test!(assign_updates_enclosing_scope, r#"fn main() {
+  let x = 1;
+  if true {
+    let y = 10;
+    if true {
+      x = x + y;
+    }
+  }
+  return x;
+}"#, Ok(Value::Number(11)));
+test!(assign_updates_nearest_declaration, r#"fn main() {
+  let x = 1;
+  if true {
+    let x = 5;
+    x = 6;
+  }
+  return x;
+}"#, Ok(Value::Number(1)));
+test!(let_in_loop_body_fresh_each_iteration, r#"fn main() {
+  let total = 0;
+  for i in 0..3 {
+    let seen = i;
+    total += seen;
+  }
+  return total;
+}"#, Ok(Value::Number(3)));
+test!(let_in_while_body_not_visible_after, r#"fn main() {
+  let i = 0;
+  while i < 2 {
+    let last = i;
+    i += 1;
+  }
+  return last;
+}"#, Err(RuntimeErrorKind::UndefinedVariable("last".to_string())));
+test!(scope_popped_on_break, r#"fn main() {
+  let x = 1;
+  while true {
+    let x = 2;
+    break;
+  }
+  return x;
+}"#, Ok(Value::Number(1)));
+test!(scope_popped_on_return, r#"fn inner() {
+  if true {
+    let x = 2;
+    return x;
+  }
+  return 0;
+}
+fn main() {
+  let x = 1;
+  let y = inner();
+  return x * 10 + y;
+}"#, Ok(Value::Number(12)));
+test!(blocks_do_not_see_callers, r#"fn peek() { if true { return x; } return 0; }
+fn main() { let x = 1; if true { return peek(); } return 0; }"#, Err(RuntimeErrorKind::UndefinedVariable("x".to_string())));
+test!(top_level_blocks_do_not_see_callers, r#"fn f() { return y; }
+let r = 0;
+if true {
+  let y = 5;
+  r = f();
+}
+r"#, Err(RuntimeErrorKind::UndefinedVariable("y".to_string())));
+test!(top_level_loop_variables_do_not_see_callers, r#"fn f() { return i; }
+let r = 0;
+for i in 7..8 {
+  r = f();
+}
+r"#, Err(RuntimeErrorKind::UndefinedVariable("i".to_string())));
+test!(top_level_blocks_not_assignable_from_callers, r#"fn f() { y = 1; return 0; }
+let r = 0;
+if true {
+  let y = 5;
+  r = f();
+}
+r"#, Err(RuntimeErrorKind::UndefinedVariable("y".to_string())));
+test!(else_branch_scoped, r#"fn main() {
+  if false {
+    let a = 1;
+  } else {
+    let a = 2;
+  }
+  return a;
+}"#, Err(RuntimeErrorKind::UndefinedVariable("a".to_string())));
+//-------Script Tests-------
+test!(script_statements_run_in_order, r#"let x = 1; x + 1"#, Ok(Value::Number(2)));
+test!(script_assignment, r#"let x = 1;
+x += 41;
+x"#, Ok(Value::Number(42)));
+test!(script_loop, r#"let total = 0;
+for i in 1..=4 {
+  total += i;
+}
+total"#, Ok(Value::Number(10)));
+test!(script_calls_function_defined_later, r#"let x = double(4);
+fn double(n) { return n * 2; }
+x"#, Ok(Value::Number(8)));
+test!(script_globals_visible_in_functions, r#"let base = 10;
+fn add(n) { return base + n; }
+add(5)"#, Ok(Value::Number(15)));
+test!(script_function_assigns_global, r#"let count = 0;
+fn bump() { count += 1; return count; }
+let first = bump();
+bump()"#, Ok(Value::Number(2)));
+test!(script_locals_shadow_globals, r#"let x = 1;
+fn f() { let x = 2; return x; }
+f() * 10 + x"#, Ok(Value::Number(21)));
+test!(script_calls_main_once, r#"let count = 0;
+fn main() { count += 1; return count; }
+main()"#, Ok(Value::Number(1)));
+test!(script_main_not_called_after_top_level_code, r#"let greeting = "hi";
+fn main() { return greeting; }
+"bye""#, Ok(Value::String("bye".to_string())));
+test!(script_main_required_without_top_level_code, r#"fn helper() { return 1; }"#, Err(RuntimeErrorKind::UndefinedFunction("main".to_string())));
+test!(script_return_ends_script, r#"let x = 3;
+if x > 2 { return "big"; }
+"small""#, Ok(Value::String("big".to_string())));
+test!(script_global_const_assigned_in_function, r#"fn reset() { limit = 0; return limit; }
+const limit = 5;
+reset()"#, Err(RuntimeErrorKind::AssignToConstant("limit".to_string())));
+test!(script_globals_not_visible_before_declared, r#"fn show() { return later; }
+let early = show();
+let later = 1;
+early"#, Err(RuntimeErrorKind::UndefinedVariable("later".to_string())));
+#[test]
+fn runtime_reused_for_another_program() {
+  let mut runtime = Runtime::new();
+  assert_eq!(runtime.execute(&program("fn main() { return 1; }").unwrap()), Ok(Value::Number(1)));
+  // The earlier program's `main` is gone, so the new script's own result is returned.
+  assert_eq!(runtime.execute(&program("let x = 2; x").unwrap()), Ok(Value::Number(2)));
+  assert_eq!(runtime.execute(&program("fn helper() { return 3; } let x = helper(); x").unwrap()), Ok(Value::Number(3)));
+  assert_eq!(runtime.execute(&program("let y = helper(); y").unwrap()).map_err(|e| e.kind), Err(RuntimeErrorKind::UndefinedFunction("helper".to_string())));
+}
+//-------Arity Tests-------
+test!(arity_too_few, r#"fn add(a, b) { return a + b; }
+fn main() { return add(1); }"#, Err(RuntimeErrorKind::ArityMismatch { function: "add".to_string(), expected: 2, found: 1 }));
+test!(arity_too_many, r#"fn add(a, b) { return a + b; }
+fn main() { return add(1, 2, 3); }"#, Err(RuntimeErrorKind::ArityMismatch { function: "add".to_string(), expected: 2, found: 3 }));
+test!(arity_none_expected, r#"fn one() { return 1; }
+fn main() { return one(1); }"#, Err(RuntimeErrorKind::ArityMismatch { function: "one".to_string(), expected: 0, found: 1 }));
+test!(arity_main_from_script, r#"fn main(x) { return x; }"#, Err(RuntimeErrorKind::ArityMismatch { function: "main".to_string(), expected: 1, found: 0 }));
+test!(arity_exact, r#"fn add(a, b) { return a + b; }
+fn main() { return add(1, 2); }"#, Ok(Value::Number(3)));
+test!(arity_in_untaken_branch, r#"fn add(a, b) { return a + b; }
+fn main() {
+  if false {
+    return add(1);
+  }
+  return 0;
+}"#, Err(RuntimeErrorKind::ArityMismatch { function: "add".to_string(), expected: 2, found: 1 }));
+test!(arity_in_arguments, r#"fn id(x) { return x; }
+id(id(1, 2))"#, Err(RuntimeErrorKind::ArityMismatch { function: "id".to_string(), expected: 1, found: 2 }));
+
+#[test]
+fn arity_error_message() {
+  let p = program(r#"fn add(a, b) { return a + b; }
+fn main() { return add(1); }"#).unwrap();
+  let error = start_interpreter(&p).unwrap_err();
+  assert_eq!(error.to_string(), "function `add` takes 2 arguments but 1 was given at 2:20 in function `main`");
+}
+
+#[test]
+fn arity_error_message_one_parameter() {
+  let p = program(r#"fn id(x) { return x; }
+id(1, 2)"#).unwrap();
+  assert_eq!(start_interpreter(&p).unwrap_err().to_string(), "function `id` takes 1 argument but 2 were given at 2:1");
+}
+
+test!(duplicate_parameter, r#"fn f(a, a) { return a; }
+f(1, 2)"#, Err(RuntimeErrorKind::DuplicateParameter("a".to_string())));
+
+#[test]
+fn duplicate_parameter_message() {
+  let p = program(r#"fn never(a, b, a) { return a; }
+1"#).unwrap();
+  assert_eq!(start_interpreter(&p).unwrap_err().to_string(), "parameter `a` is declared more than once at 1:16 in function `never`");
+}
+
+#[test]
+fn parameters_must_be_names() {
+  let error = program("fn f(1) { return 2; } f(1)").unwrap_err();
+  assert_eq!((error.line, error.column), (1, 6));
+  assert_eq!(error.expected, vec!["parameter name", "`)` after parameters"]);
+  let error = program("fn f(a, b + 1) { return a; }").unwrap_err();
+  assert_eq!((error.line, error.column), (1, 11));
+}
+test!(call_in_arguments, r#"fn add(a, b) { return a + b; }
+fn main() { return add(add(1, 2), add(add(3, 4), 5)); }"#, Ok(Value::Number(15)));
+test!(recursive_call_in_arguments, r#"fn sum(n) {
+  if n == 0 {
+    return 0;
+  }
+  return n + sum(n - 1);
+}
+fn main() { return sum(sum(3)); }"#, Ok(Value::Number(21)));
+//-------Benchmark Tests-------
+// Recursive fibonacci, with `padding` extra statements in the function body that are never run.
+fn fibonacci_source(padding: usize) -> String {
+  let unused = "    let unused = 1;\n".repeat(padding);
+  format!(r#"fn fib(n) {{
+  if n < 2 {{
+    return n;
+  }}
+  if false {{
+{}  }}
+  return fib(n - 1) + fib(n - 2);
+}}
+fn main() {{ return fib(20); }}"#, unused)
+}
+
+#[test]
+fn fibonacci_work_independent_of_body_size() {
+  let work = |padding| {
+    let p = program(&fibonacci_source(padding)).unwrap();
+    let mut runtime = Runtime::new();
+    let (result, allocated) = allocations(|| runtime.execute(&p));
+    assert_eq!(result, Ok(Value::Number(6765)));
+    (runtime.fuel_used(), allocated)
+  };
+  let (small, large) = (work(0), work(1000));
+  // The padding is never executed, so calls evaluate exactly the same nodes however large the body is.
+  assert_eq!(small.0, large.0);
+  // The body is prepared once, taking a few allocations per statement, rather than copied for each of the 21891 calls.
+  assert!(large.1 < small.1 + 10 * 1000, "a larger body made calls allocate more: {} vs {}", large.1, small.1);
+}
+
+// Timing depends on the machine and its load, so this only runs when asked for with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn benchmark_fibonacci() {
+  use std::time::Instant;
+  let time = |padding| {
+    let p = program(&fibonacci_source(padding)).unwrap();
+    let start = Instant::now();
+    assert_eq!(start_interpreter(&p), Ok(Value::Number(6765)));
+    start.elapsed()
+  };
+  let small = time(0);
+  let large = time(1000);
+  println!("fib(20): {:?} with a small body, {:?} with 1000 more statements", small, large);
+  // The padding is never executed, so the cost of a call must not grow with it.
+  assert!(large < small * 3, "a larger body made calls slower: {:?} vs {:?}", large, small);
+}
+//-------Call Depth Tests-------
+const RUNAWAY: &str = r#"fn forever(n) { return 1 + forever(n + 1); }
+fn main() { let total = forever(0); return total; }"#;
+
+#[test]
+fn call_depth_limit() {
+  let p = program(RUNAWAY).unwrap();
+  let error = Runtime::new().with_max_call_depth(5).execute(&p).unwrap_err();
+  let calls = vec!["main", "forever", "forever", "forever", "forever", "forever"].into_iter().map(String::from).collect();
+  assert_eq!(error.kind, RuntimeErrorKind::StackOverflow { depth: 5, calls });
+  assert_eq!(error.function, Some("forever".to_string()));
+  assert_eq!(error.to_string(), "stack overflow: more than 5 nested calls (main -> forever x5) at 1:28 in function `forever`");
+}
+
+#[test]
+fn call_depth_limit_allows_exact_depth() {
+  let p = program(r#"fn down(n) { if n == 0 { return "done"; } let rest = down(n - 1); return rest; }
+fn main() { return down(3); }"#).unwrap();
+  // Four calls to `down`. The first is a tail call, so it replaces `main` rather than nesting inside it.
+  assert_eq!(Runtime::new().with_max_call_depth(4).execute(&p), Ok(Value::String("done".to_string())));
+  assert!(matches!(Runtime::new().with_max_call_depth(3).execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::StackOverflow { depth: 3, .. })));
+}
+
+#[test]
+fn call_depth_counts_calls_from_script() {
+  let p = program(r#"fn ping(n) { return 1 + pong(n); }
+fn pong(n) { return 1 + ping(n); }
+ping(1)"#).unwrap();
+  let error = Runtime::new().with_max_call_depth(4).execute(&p).unwrap_err();
+  assert_eq!(error.to_string(), "stack overflow: more than 4 nested calls (ping -> pong -> ping -> pong -> ping) at 2:25 in function `pong`");
+}
+
+// Run `source` with the default limits on a thread with the default host stack size.
+fn run_on_default_thread(source: String) -> Result<Value, RuntimeError> {
+  std::thread::spawn(move || start_interpreter(&program(&source).unwrap())).join().unwrap()
+}
+
+#[test]
+fn call_depth_default_stops_runaway_recursion() {
+  match run_on_default_thread(RUNAWAY.to_string()).map_err(|e| e.kind) {
+    Err(RuntimeErrorKind::StackOverflow { depth, calls }) => {
+      assert_eq!(calls.len(), depth + 1);
+      assert!(calls.iter().skip(1).all(|call| call == "forever"));
+    },
+    other => panic!("expected a stack overflow, got {:?}", other),
+  }
+}
+
+#[test]
+fn call_depth_default_reported_as_stack_overflow() {
+  let source = "fn f(n) { if n == 0 { return 0; } return 1 + f(n - 1); } f(120)".to_string();
+  assert!(matches!(run_on_default_thread(source).map_err(|e| e.kind), Err(RuntimeErrorKind::StackOverflow { .. })));
+}
+
+#[test]
+fn nesting_default_stops_recursion_inside_blocks() {
+  let source = format!("fn forever(n) {{ {} let total = 1 + forever(n + 1); return total; {} return 0; }}
+fn main() {{ let total = forever(0); return total; }}", "while true { if true { for i in 0..1 { ".repeat(10), "} } } ".repeat(10));
+  assert!(matches!(run_on_default_thread(source).map_err(|e| e.kind), Err(RuntimeErrorKind::StackOverflow { .. })));
+}
+
+#[test]
+fn nesting_limit() {
+  let p = program("let x = ---1; x").unwrap();
+  // The program, the `let` statement, its expression and the three negations are each evaluated inside the one before.
+  let nested = 1 + 1 + 1 + 1 + 3 + 1;
+  assert_eq!(Runtime::new().with_max_nesting_depth(nested).execute(&p), Ok(Value::Number(-1)));
+  let error = Runtime::new().with_max_nesting_depth(nested - 1).execute(&p).unwrap_err();
+  assert_eq!(error.kind, RuntimeErrorKind::NestingTooDeep { depth: nested - 1, calls: vec![] });
+  assert_eq!(error.to_string(), format!("stack overflow: more than {} nested evaluations at 1:12", nested - 1));
+}
+
+#[test]
+fn nesting_limit_names_call() {
+  let p = program("fn main() { return ---1; }").unwrap();
+  let error = Runtime::new().with_max_nesting_depth(6).execute(&p).unwrap_err();
+  assert_eq!(error.kind, RuntimeErrorKind::NestingTooDeep { depth: 6, calls: vec!["main".to_string()] });
+  assert_eq!(error.to_string(), "stack overflow: more than 6 nested evaluations (main) at 1:21 in function `main`");
+}
+
+#[test]
+fn nesting_limit_in_recursion_is_stack_overflow() {
+  let p = program(RUNAWAY).unwrap();
+  let error = Runtime::new().with_max_nesting_depth(20).execute(&p).unwrap_err();
+  assert!(error.to_string().starts_with("stack overflow: more than 3 nested calls (main -> forever x3)"), "{}", error);
+}
+
+#[test]
+fn parse_nesting_limit() {
+  let source = format!("{}1", "-".repeat(5000));
+  let error = std::thread::spawn(move || program(&source).map(|_| ())).join().unwrap().unwrap_err();
+  assert_eq!(error.expected, vec!["less deeply nested code"]);
+  assert_eq!(error.column, asalang::MAX_PARSE_DEPTH + 1);
+  let blocks = format!("{}let x = 1;{}", "if true {\n".repeat(5000), "}\n".repeat(5000));
+  assert!(std::thread::spawn(move || program(&blocks).is_err()).join().unwrap());
+}
+
+test!(parse_nesting_within_limit, r#"((((((((((((((((((((((((((((((1))))))))))))))))))))))))))))))"#, Ok(Value::Number(1)));
+//-------Tail Call Tests-------
+// Run `source` on a thread with a small host stack, so that recursion that is not eliminated overflows it.
+fn run_with_small_stack(source: &'static str, max_call_depth: usize) -> Result<Value, RuntimeErrorKind> {
+  let run = std::thread::Builder::new().stack_size(1 << 20).spawn(move || {
+    let p = program(source).unwrap();
+    Runtime::new().with_max_call_depth(max_call_depth).execute(&p).map_err(|e| e.kind)
+  });
+  run.unwrap().join().unwrap()
+}
+
+#[test]
+fn tail_call_countdown_from_one_million() {
+  let source = r#"fn countdown(n) {
+  if n == 0 {
+    return "liftoff";
+  }
+  return countdown(n - 1);
+}
+fn main() { return countdown(1000000); }"#;
+  assert_eq!(run_with_small_stack(source, 10), Ok(Value::String("liftoff".to_string())));
+}
+
+#[test]
+fn tail_call_accumulator() {
+  let source = r#"fn sum(n, total) {
+  if n == 0 {
+    return total;
+  }
+  return sum(n - 1, total + n);
+}
+sum(50000, 0)"#;
+  assert_eq!(run_with_small_stack(source, 10), Ok(Value::Number(1250025000)));
+}
+
+#[test]
+fn tail_call_mutual_recursion() {
+  let source = r#"fn is_even(n) {
+  if n == 0 {
+    return true;
+  }
+  return is_odd(n - 1);
+}
+fn is_odd(n) {
+  if n == 0 {
+    return false;
+  }
+  return is_even(n - 1);
+}
+fn main() { return is_even(100001); }"#;
+  assert_eq!(run_with_small_stack(source, 10), Ok(Value::Bool(false)));
+}
+
+#[test]
+fn tail_call_from_inside_loop() {
+  let source = r#"fn spin(n) {
+  while true {
+    for i in 0..3 {
+      if n == 0 {
+        return i;
+      }
+      return spin(n - 1);
+    }
+  }
+  return -1;
+}
+fn main() { return spin(100000); }"#;
+  assert_eq!(run_with_small_stack(source, 10), Ok(Value::Number(0)));
+}
+
+#[test]
+fn non_tail_call_still_limited() {
+  let source = r#"fn count(n) {
+  if n == 0 {
+    return 0;
+  }
+  return 1 + count(n - 1);
+}
+fn main() { return count(100); }"#;
+  assert!(matches!(run_with_small_stack(source, 10), Err(RuntimeErrorKind::StackOverflow { depth: 10, .. })));
+}
+
+test!(tail_call_arguments_use_caller_scope, r#"fn add(a, b) { return a + b; }
+fn main() {
+  let x = 2;
+  if true {
+    let y = 3;
+    return add(x, y);
+  }
+  return 0;
+}"#, Ok(Value::Number(5)));
+test!(tail_call_undefined_function, r#"fn main() { return missing(1); }"#, Err(RuntimeErrorKind::UndefinedFunction("missing".to_string())));
+
+#[test]
+fn tail_call_errors_name_the_callee() {
+  let p = program(r#"fn fail(n) { return n + true; }
+fn main() { return fail(1); }"#).unwrap();
+  let error = start_interpreter(&p).unwrap_err();
+  assert_eq!(error.to_string(), "cannot apply `+` to number and bool at 1:21 in function `fail`");
+  let p = program(r#"fn main() { return missing(1); }"#).unwrap();
+  assert_eq!(start_interpreter(&p).unwrap_err().to_string(), "undefined function `missing` at 1:20 in function `main`");
+}
+//-------Fuel Tests-------
+#[test]
+fn fuel_stops_infinite_loop() {
+  let p = program(r#"fn main() {
+  let i = 0;
+  while true {
+    i += 1;
+  }
+  return i;
+}"#).unwrap();
+  let mut runtime = Runtime::new().with_fuel(10000);
+  let error = runtime.execute(&p).unwrap_err();
+  assert_eq!(error.kind, RuntimeErrorKind::OutOfFuel);
+  assert_eq!(error.function, Some("main".to_string()));
+  assert_eq!(runtime.remaining_fuel(), Some(0));
+  assert_eq!(runtime.fuel_used(), 10000);
+}
+
+#[test]
+fn fuel_stops_runaway_recursion() {
+  let p = program(r#"fn spin(n) { return spin(n + 1); }
+fn main() { return spin(0); }"#).unwrap();
+  assert_eq!(Runtime::new().with_fuel(5000).execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::OutOfFuel));
+}
+
+#[test]
+fn fuel_counts_each_node() {
+  // The program, the expression, the `+` and its two operands.
+  let p = program("1 + 2").unwrap();
+  let mut runtime = Runtime::new().with_fuel(5);
+  assert_eq!(runtime.execute(&p), Ok(Value::Number(3)));
+  assert_eq!(runtime.fuel_used(), 5);
+  assert_eq!(runtime.remaining_fuel(), Some(0));
+  let mut runtime = Runtime::new().with_fuel(4);
+  assert_eq!(runtime.execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::OutOfFuel));
+}
+
+#[test]
+fn fuel_use_is_deterministic() {
+  let source = r#"fn fib(n) {
+  if n < 2 {
+    return n;
+  }
+  return fib(n - 1) + fib(n - 2);
+}
+fn main() { return fib(10); }"#;
+  let used = |fuel| {
+    let mut runtime = Runtime::new().with_fuel(fuel);
+    assert_eq!(runtime.execute(&program(source).unwrap()), Ok(Value::Number(55)));
+    runtime.fuel_used()
+  };
+  let unlimited = {
+    let mut runtime = Runtime::new();
+    runtime.execute(&program(source).unwrap()).unwrap();
+    assert_eq!(runtime.remaining_fuel(), None);
+    runtime.fuel_used()
+  };
+  assert_eq!(used(1_000_000), unlimited);
+  assert_eq!(used(unlimited), unlimited);
+}
+
+#[test]
+fn fuel_can_be_topped_up() {
+  let p = program(r#"let total = 0;
+for i in 0..100 {
+  total += i;
+}
+total"#).unwrap();
+  let mut runtime = Runtime::new().with_fuel(50);
+  assert_eq!(runtime.execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::OutOfFuel));
+  runtime.add_fuel(10000);
+  let before = runtime.fuel_used();
+  assert_eq!(runtime.execute(&p), Ok(Value::Number(4950)));
+  assert_eq!(runtime.remaining_fuel(), Some(10000 - (runtime.fuel_used() - before)));
+}
+//-------Memory Tests-------
+const VALUE: usize = std::mem::size_of::<Value>();
+
+#[test]
+fn memory_limit_on_variables() {
+  let p = program(r#"fn main() {
+  let a = "0123456789";
+  let b = "0123456789";
+  let c = "0123456789";
+  return a;
+}"#).unwrap();
+  let each = 1 + VALUE + 10;
+  assert_eq!(Runtime::new().with_memory_limit(3 * each).execute(&p), Ok(Value::String("0123456789".to_string())));
+  let mut runtime = Runtime::new().with_memory_limit(3 * each - 1);
+  let error = runtime.execute(&p).unwrap_err();
+  assert_eq!(error.kind, RuntimeErrorKind::MemoryLimitExceeded { limit: 3 * each - 1 });
+  assert_eq!(error.to_string(), format!("memory limit of {} bytes exceeded at 4:3 in function `main`", 3 * each - 1));
+  // Everything the failed program held has been let go.
+  assert_eq!(runtime.memory_used(), 0);
+}
+
+#[test]
+fn memory_limit_on_temporaries() {
+  let p = program(r#""a string that is never stored anywhere""#).unwrap();
+  assert_eq!(Runtime::new().with_memory_limit(VALUE).execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::MemoryLimitExceeded { limit: VALUE }));
+  assert!(Runtime::new().with_memory_limit(VALUE + 64).execute(&p).is_ok());
+}
+
+#[test]
+fn memory_released_when_scopes_end() {
+  // Each iteration declares a variable in the loop body, which is dropped again before the next one.
+  let p = program(r#"fn main() {
+  let count = 0;
+  for i in 0..1000 {
+    let text = "some text to hold on to";
+    count += 1;
+  }
+  return count;
+}"#).unwrap();
+  let mut runtime = Runtime::new().with_memory_limit(512);
+  assert_eq!(runtime.execute(&p), Ok(Value::Number(1000)));
+  assert_eq!(runtime.memory_used(), 0);
+}
+
+#[test]
+fn memory_held_by_recursion() {
+  // Every call keeps its parameter and local alive until it returns.
+  let p = program(r#"fn deep(n) {
+  if n == 0 {
+    return 0;
+  }
+  let padding = "..........";
+  return 1 + deep(n - 1);
+}
+fn main() { return deep(20); }"#).unwrap();
+  let per_call = (1 + VALUE) + (7 + VALUE + 10);
+  let run = |limit| Runtime::new().with_memory_limit(limit).execute(&p).map_err(|e| e.kind);
+  assert_eq!(run(21 * per_call), Ok(Value::Number(20)));
+  assert_eq!(run(10 * per_call), Err(RuntimeErrorKind::MemoryLimitExceeded { limit: 10 * per_call }));
+}
+
+#[test]
+fn memory_assignment_replaces_value() {
+  let p = program(r#"let s = "a";
+for i in 0..100 {
+  s = "a longer string than the one before";
+}
+s"#).unwrap();
+  let mut runtime = Runtime::new().with_memory_limit(4 * VALUE + 64);
+  assert!(runtime.execute(&p).is_ok());
+  assert_eq!(runtime.memory_used(), 0);
+}
+
+#[test]
+fn memory_limit_on_string_iteration() {
+  let p = program(r#"let count = 0;
+for c in "abcdefghijklmnopqrstuvwxyz" {
+  count += 1;
+}
+count"#).unwrap();
+  // Enough for `count` and the string, but not for the copy of its 26 characters the loop steps through.
+  let limit = (5 + VALUE) + (VALUE + 26);
+  assert_eq!(Runtime::new().with_memory_limit(limit).execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::MemoryLimitExceeded { limit }));
+  assert_eq!(Runtime::new().with_memory_limit(1024).execute(&p), Ok(Value::Number(26)));
+}
+
+//-------Cancellation Tests-------
+// Cancel `handle` from another thread after a short wait, joining that thread when the returned function is called.
+fn cancel_soon(handle: CancelHandle) -> impl FnOnce() {
+  let canceller = std::thread::spawn(move || {
+    std::thread::sleep(Duration::from_millis(20));
+    handle.cancel();
+  });
+  move || canceller.join().unwrap()
+}
+
+#[test]
+fn cancel_from_another_thread() {
+  let p = program("while true { }").unwrap();
+  let mut runtime = Runtime::new();
+  let canceller = cancel_soon(runtime.cancel_handle());
+  assert_eq!(runtime.execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::Cancelled));
+  canceller();
+  // The cancellation only stops the program it was for.
+  assert_eq!(runtime.execute(&program("let x = 1; x + 1").unwrap()), Ok(Value::Number(2)));
+}
+
+#[test]
+fn cancel_before_execute() {
+  let mut runtime = Runtime::new();
+  let handle = runtime.cancel_handle();
+  // Cancelling before the program starts still stops it, but not the one after it.
+  handle.cancel();
+  assert_eq!(runtime.execute(&program("while true { }").unwrap()).map_err(|e| e.kind), Err(RuntimeErrorKind::Cancelled));
+  assert_eq!(runtime.execute(&program("let x = 1; x").unwrap()), Ok(Value::Number(1)));
+}
+
+#[test]
+fn cancel_after_finished() {
+  let mut runtime = Runtime::new();
+  let handle = runtime.cancel_handle();
+  let p = program("let x = 1; x").unwrap();
+  assert_eq!(runtime.execute(&p), Ok(Value::Number(1)));
+  // The handle's program has finished, so this does not stop the next one.
+  handle.cancel();
+  assert_eq!(runtime.execute(&p), Ok(Value::Number(1)));
+  // Neither does cancelling with an old handle after a newer one was used.
+  let newer = runtime.cancel_handle();
+  newer.cancel();
+  handle.cancel();
+  assert_eq!(runtime.execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::Cancelled));
+  assert_eq!(runtime.execute(&p), Ok(Value::Number(1)));
+}
+
+#[test]
+fn cancel_program_that_fails_checks() {
+  let mut runtime = Runtime::new();
+  runtime.cancel_handle().cancel();
+  assert!(matches!(runtime.execute(&program("fn f() { return 1; } f(2)").unwrap()).map_err(|e| e.kind), Err(RuntimeErrorKind::ArityMismatch { .. })));
+  // The cancellation was for the program that failed, not this one.
+  assert_eq!(runtime.execute(&program("let x = 1; x").unwrap()), Ok(Value::Number(1)));
+}
+
+#[test]
+fn cancel_unwinds_calls() {
+  let p = program(r#"fn spin(n) {
+  while true { }
+  return n;
+}
+fn main() { return 1 + spin(1); }"#).unwrap();
+  let mut runtime = Runtime::new().with_memory_limit(1024);
+  let canceller = cancel_soon(runtime.cancel_handle());
+  assert_eq!(runtime.execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::Cancelled));
+  canceller();
+  assert_eq!(runtime.memory_used(), 0);
+}
+
+#[test]
+fn timeout_stops_infinite_loop() {
+  let p = program("let n = 0; while true { n += 1; }").unwrap();
+  let mut runtime = Runtime::new().with_timeout(Duration::from_millis(50));
+  assert_eq!(runtime.execute(&p).map_err(|e| e.kind), Err(RuntimeErrorKind::TimedOut));
+  // Every program gets the whole timeout to itself.
+  assert_eq!(runtime.execute(&program("let x = 1; x").unwrap()), Ok(Value::Number(1)));
+}
+
+#[test]
+fn timeout_not_reached() {
+  let p = program("let n = 0; for i in 0..1000 { n += i; } n").unwrap();
+  assert_eq!(Runtime::new().with_timeout(Duration::from_secs(60)).execute(&p), Ok(Value::Number(499500)));
+}
+
+//-------Arithmetic Tests-------
+test!(divide_by_zero, r#"1 / 0"#, Err(RuntimeErrorKind::DivisionByZero));
+test!(divide_by_zero_variable, r#"fn main() { let zero = 5 - 5; return 10 / zero; }"#, Err(RuntimeErrorKind::DivisionByZero));
+test!(add_overflow, r#"2147483647 + 1"#, Err(RuntimeErrorKind::Overflow("+".to_string())));
+test!(subtract_overflow, r#"-2147483647 - 2"#, Err(RuntimeErrorKind::Overflow("-".to_string())));
+test!(multiply_overflow, r#"65536 * 65536"#, Err(RuntimeErrorKind::Overflow("*".to_string())));
+test!(divide_overflow, r#"let min = -2147483648; min / -1"#, Err(RuntimeErrorKind::Overflow("/".to_string())));
+test!(negate_overflow, r#"let min = -2147483648; -min"#, Err(RuntimeErrorKind::Overflow("-".to_string())));
+test!(arithmetic_at_limits, r#"let min = -2147483648; min + 2147483647"#, Ok(Value::Number(-1)));
+test!(exponent_overflow, r#"2 ^ 31"#, Err(RuntimeErrorKind::Overflow("^".to_string())));
+test!(exponent_largest, r#"-2 ^ 31"#, Err(RuntimeErrorKind::Overflow("^".to_string())));
+test!(exponent_negative_base, r#"(-2) ^ 31"#, Ok(Value::Number(i32::MIN)));
+test!(exponent_zero, r#"0 ^ 0"#, Ok(Value::Number(1)));
+test!(exponent_negative, r#"2 ^ -1"#, Err(RuntimeErrorKind::NegativeExponent));
+test!(exponent_huge_overflows_quickly, r#"2 ^ 1000000000"#, Err(RuntimeErrorKind::Overflow("^".to_string())));
+test!(min_literal, "-2147483648", Ok(Value::Number(i32::MIN)));
+test!(min_literal_times_one, "-2147483648 * 1", Ok(Value::Number(i32::MIN)));
+test!(min_literal_negated, "--2147483648", Err(RuntimeErrorKind::Overflow("-".to_string())));
+
+#[test]
+fn min_literal_only_as_whole_operand() {
+  for (source, column) in [("2147483648", 1), ("1 - 2147483648", 5), ("-2147483648 ^ 2", 2)] {
+    let error = program(source).unwrap_err();
+    assert_eq!((error.column, error.expected), (column, vec!["number no larger than 2147483647"]), "{}", source);
+  }
+  assert_eq!(program("2147483649").unwrap_err().expected, vec!["number no larger than 2147483647"]);
+}
+test!(exponent_huge_of_one, r#"1 ^ 2147483647"#, Ok(Value::Number(1)));
+test!(exponent_huge_of_minus_one, r#"(-1) ^ 2147483647"#, Ok(Value::Number(-1)));
+
+#[test]
+fn division_by_zero_location() {
+  let p = program(r#"let x = 0;
+let y = 7 / x;"#).unwrap();
+  let error = start_interpreter(&p).unwrap_err();
+  assert_eq!(error.span, Some(Span { start: 19, end: 24, line: 2, column: 9 }));
+  assert_eq!(error.to_string(), "division by zero at 2:9");
+}